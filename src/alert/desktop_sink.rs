@@ -0,0 +1,34 @@
+use std::error::Error;
+
+use notify_rust::{Notification, Urgency};
+
+use super::*;
+
+impl DesktopSink {
+    pub fn new(app_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+        }
+    }
+}
+
+impl AlertSink for DesktopSink {
+    fn name(&self) -> String {
+        "desktop".to_string()
+    }
+
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        let urgency = match event.severity() {
+            Severity::Info => Urgency::Normal,
+            Severity::Critical => Urgency::Critical,
+        };
+        let summary = format!("{} on {}", event.name(), event.context().interface);
+        Notification::new()
+            .appname(&self.app_name)
+            .summary(&summary)
+            .urgency(urgency)
+            .body(&event.to_string())
+            .show()?;
+        Ok(())
+    }
+}
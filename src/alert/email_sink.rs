@@ -0,0 +1,78 @@
+use std::error::Error;
+
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, Message, Transport,
+};
+
+use crate::config::{EmailConfig, SmtpTls};
+
+use super::*;
+
+impl EmailSink {
+    pub fn new(config: &EmailConfig) -> Result<Self, Box<dyn Error>> {
+        let mut builder = match config.tls {
+            SmtpTls::None => SmtpTransport::builder_dangerous(&config.host),
+            SmtpTls::Starttls => SmtpTransport::starttls_relay(&config.host)?,
+            SmtpTls::Tls => SmtpTransport::relay(&config.host)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let transport = builder.timeout(Some(config.timeout())).build();
+
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| format!("email sender {}: {e}", config.from))?;
+        let to = config
+            .to
+            .iter()
+            .map(|to| to.parse().map_err(|e| format!("email recipient {to}: {e}")))
+            .collect::<Result<Vec<Mailbox>, String>>()?;
+        if to.is_empty() {
+            return Err(format!("no recipient for email alerts through {}", config.host).into());
+        }
+
+        Ok(Self {
+            transport,
+            from,
+            to,
+            host: config.host.clone(),
+        })
+    }
+}
+
+impl AlertSink for EmailSink {
+    fn name(&self) -> String {
+        format!("email through {}", self.host)
+    }
+
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        let context = event.context();
+        let text = event.to_string();
+        let subject = format!(
+            "[arp-watch] {} {}: {}",
+            event.severity(),
+            event.name(),
+            text.lines().next().unwrap_or_default()
+        );
+        let body = format!(
+            "{}\n\nInterface: {}\nTime: {}\n",
+            text, context.interface, context.timestamp
+        );
+
+        let mut message = Message::builder().from(self.from.clone());
+        for to in self.to.iter() {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
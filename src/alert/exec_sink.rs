@@ -0,0 +1,163 @@
+use std::{
+    error::Error,
+    process::{Command, Stdio},
+    thread,
+    time::Instant,
+};
+
+use serde_json::Value;
+
+use super::*;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl ExecSink {
+    pub fn new(config: ExecConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl AlertSink for ExecSink {
+    fn name(&self) -> String {
+        format!("exec {}", self.config.command.display())
+    }
+
+    /// Run the command with the event in `ARP_*` environment variables,
+    /// killing it once the timeout is reached
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .envs(event_env(event)?)
+            // Output would land over the TUI
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return match status.success() {
+                    true => Ok(()),
+                    false => Err(format!("exited with {status}").into()),
+                };
+            }
+            if started.elapsed() > self.config.timeout() {
+                child.kill()?;
+                child.wait()?;
+                return Err("timed out".into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// `ARP_EVENT`, `ARP_SEVERITY`, `ARP_MESSAGE`, `ARP_JSON` and one variable per
+/// event field, e.g. `ARP_IP`, `ARP_OLD` or `ARP_CONTEXT_INTERFACE`
+fn event_env(event: &ArpEvent) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let json = serde_json::to_value(event)?;
+    let mut env = vec![
+        ("ARP_SEVERITY".to_string(), event.severity().to_string()),
+        ("ARP_MESSAGE".to_string(), event.to_string()),
+        ("ARP_JSON".to_string(), json.to_string()),
+    ];
+    field_env("ARP", &json, &mut env);
+    Ok(env)
+}
+
+/// Variables of the fields of `value`, named after their path from the
+/// event so that nested fields cannot clash
+fn field_env(prefix: &str, value: &Value, env: &mut Vec<(String, String)>) {
+    let Value::Object(object) = value else {
+        return;
+    };
+    for (key, value) in object.iter() {
+        let name = format!("{prefix}_{}", key.to_uppercase());
+        let value = match value {
+            Value::Null => continue,
+            Value::Object(_) => {
+                field_env(&name, value, env);
+                continue;
+            }
+            Value::String(value) => value.clone(),
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+            value => value.to_string(),
+        };
+        env.push((name, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        event::{Anomaly, EventContext},
+        test_util::*,
+    };
+
+    use super::*;
+
+    fn anomaly() -> ArpEvent {
+        ArpEvent::Anomaly {
+            context: EventContext::new(at(0), "eth0", None),
+            ip: Some(Ipv4Addr::new(10, 0, 0, 2).into()),
+            anomaly: Anomaly::ForeignSender {
+                ip: Ipv4Addr::new(192, 168, 1, 2),
+            },
+            count: 3,
+            frame: "ffff".into(),
+        }
+    }
+
+    #[test]
+    fn nested_fields_prefixed() {
+        let env = event_env(&anomaly()).unwrap();
+        let var = |name: &str| {
+            env.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("ARP_EVENT"), Some("Anomaly"));
+        assert_eq!(var("ARP_IP"), Some("10.0.0.2"));
+        assert_eq!(var("ARP_ANOMALY_KIND"), Some("foreign_sender"));
+        assert_eq!(var("ARP_ANOMALY_IP"), Some("192.168.1.2"));
+        assert_eq!(var("ARP_CONTEXT_INTERFACE"), Some("eth0"));
+        assert_eq!(var("ARP_CONTEXT_SOURCE"), None);
+        assert_eq!(var("ARP_COUNT"), Some("3"));
+
+        let violation = ArpEvent::TrustedViolation {
+            context: EventContext::new(at(0), "eth0", None),
+            ip: Ipv4Addr::new(10, 0, 0, 1).into(),
+            expected: vec![mac_addr(1), mac_addr(2)],
+            actual: mac_addr(3),
+        };
+        let env = event_env(&violation).unwrap();
+        assert!(env.contains(&(
+            "ARP_EXPECTED".to_string(),
+            "02:00:00:00:00:01 02:00:00:00:00:02".to_string()
+        )));
+    }
+
+    #[test]
+    fn hook_environment() {
+        let mut sink = ExecSink::new(ExecConfig {
+            command: "/bin/sh".into(),
+            args: vec![
+                "-c".into(),
+                "echo noise; test \"$ARP_CONTEXT_INTERFACE $ARP_ANOMALY_IP\" = \"eth0 192.168.1.2\""
+                    .into(),
+            ],
+            min_severity: Severity::Info,
+            timeout: 5,
+        });
+        sink.send(&anomaly()).unwrap();
+    }
+}
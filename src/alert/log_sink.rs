@@ -0,0 +1,21 @@
+use std::error::Error;
+
+use super::*;
+
+impl AlertSink for LogSink {
+    fn name(&self) -> String {
+        "log".to_string()
+    }
+
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        match event.severity() {
+            Severity::Info => info!("{}", event),
+            Severity::Critical => error!("{}", event),
+        }
+        Ok(())
+    }
+
+    fn rate_limited(&self) -> bool {
+        false
+    }
+}
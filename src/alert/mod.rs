@@ -0,0 +1,265 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use lettre::{message::Mailbox, SmtpTransport};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use syslog::{Formatter3164, LoggerBackend};
+use tokio::sync::{broadcast::error::RecvError, watch};
+
+use crate::{
+    config::{AlertConfig, Config, ExecConfig, WebhookConfig},
+    event::{ArpEvent, EventReceiver},
+};
+
+pub mod desktop_sink;
+pub mod email_sink;
+pub mod exec_sink;
+pub mod log_sink;
+pub mod syslog_sink;
+pub mod webhook_sink;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertBackend {
+    /// Desktop notification through dbus
+    Desktop,
+    /// Application log
+    Log,
+    /// Local syslog socket, picked up by journald
+    Syslog,
+}
+
+#[derive(
+    Debug, Default, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Churn on bindings nobody vouched for
+    #[default]
+    Info,
+    /// Spoofing or a conflict: a pinned binding contradicted, duplicate
+    /// IPs, flapping, rogue routers
+    Critical,
+}
+
+/// Somewhere alerts can be delivered
+pub trait AlertSink: Send {
+    fn name(&self) -> String;
+
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>>;
+
+    /// Whether `rate_limit` applies, local logs are always written
+    fn rate_limited(&self) -> bool {
+        true
+    }
+}
+
+pub struct DesktopSink {
+    app_name: String,
+}
+
+pub struct LogSink;
+
+pub struct SyslogSink {
+    logger: syslog::Logger<LoggerBackend, Formatter3164>,
+}
+
+pub struct WebhookSink {
+    agent: ureq::Agent,
+    config: WebhookConfig,
+}
+
+pub struct EmailSink {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    host: String,
+}
+
+pub struct ExecSink {
+    config: ExecConfig,
+}
+
+/// A sink along with its severity filter and rate limiting state
+struct SinkSlot {
+    sink: Box<dyn AlertSink>,
+    min_severity: Severity,
+    sent: VecDeque<Instant>,
+    suppressed: u32,
+}
+
+/// Dispatches events to every configured sink
+pub struct Alerter {
+    sinks: Vec<SinkSlot>,
+    /// Alerts allowed per `rate_window` and per sink, 0 for no limit
+    rate_limit: u32,
+    rate_window: Duration,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl Alerter {
+    pub fn new(config: &AlertConfig) -> Self {
+        let mut alerter = Self {
+            sinks: vec![],
+            rate_limit: 0,
+            rate_window: Duration::ZERO,
+        };
+        alerter.configure(config);
+        alerter
+    }
+
+    /// Rebuild the sinks, a sink failing to set up is left out
+    pub fn configure(&mut self, config: &AlertConfig) {
+        self.rate_limit = config.rate_limit;
+        self.rate_window = config.rate_window();
+        self.sinks.clear();
+
+        for backend in config.backends.iter().flatten() {
+            let sink: Result<Box<dyn AlertSink>, Box<dyn Error>> = match backend {
+                AlertBackend::Desktop => Ok(Box::new(DesktopSink::new(&config.app_name))),
+                AlertBackend::Log => Ok(Box::new(LogSink)),
+                AlertBackend::Syslog => SyslogSink::new().map(|sink| Box::new(sink) as _),
+            };
+            let min_severity = config
+                .min_severity
+                .get(backend)
+                .copied()
+                .unwrap_or_default();
+            self.add(sink, min_severity);
+        }
+        for webhook in config.webhook.iter() {
+            let sink = Ok(Box::new(WebhookSink::new(webhook.clone())) as _);
+            self.add(sink, webhook.min_severity);
+        }
+        for email in config.email.iter() {
+            let sink = EmailSink::new(email).map(|sink| Box::new(sink) as _);
+            self.add(sink, email.min_severity);
+        }
+        for exec in config.exec.iter() {
+            let sink = Ok(Box::new(ExecSink::new(exec.clone())) as _);
+            self.add(sink, exec.min_severity);
+        }
+    }
+
+    fn add(&mut self, sink: Result<Box<dyn AlertSink>, Box<dyn Error>>, min_severity: Severity) {
+        match sink {
+            Ok(sink) => {
+                info!("Alerting {} from {} severity", sink.name(), min_severity);
+                self.sinks.push(SinkSlot {
+                    sink,
+                    min_severity,
+                    sent: VecDeque::new(),
+                    suppressed: 0,
+                });
+            }
+            Err(e) => error!("Alert backend disabled: {e}"),
+        }
+    }
+
+    pub fn event(&mut self, event: &ArpEvent) {
+        match event {
+            // Vouched for, nothing to report
+            ArpEvent::NewHost { trusted: true, .. } => (),
+            // Reported once the probe tells what happened
+            ArpEvent::MacChanged {
+                verifying: true, ..
+            } => (),
+            // Announcements are routine, the binding change itself is reported
+            ArpEvent::GratuitousArp { .. } => (),
+            // Hosts joining the network, only worth an alert on a taken IP
+            ArpEvent::AddressProbe { holders, .. } if holders.is_empty() => info!("{}", event),
+            ArpEvent::ScanCompleted { .. } => info!("{}", event),
+            _ => self.send(event),
+        }
+    }
+
+    /// Deliver to every sink accepting the event severity, a critical alert
+    /// nobody received ends up in the application log
+    pub fn send(&mut self, event: &ArpEvent) {
+        let severity = event.severity();
+        let mut delivered = false;
+        for slot in self.sinks.iter_mut() {
+            if severity < slot.min_severity {
+                continue;
+            }
+            if slot.sink.rate_limited() && !slot.allow(self.rate_limit, self.rate_window) {
+                continue;
+            }
+            match slot.sink.send(event) {
+                Ok(()) => delivered = true,
+                Err(e) => error!("Failed to alert {}: {e}", slot.sink.name()),
+            }
+        }
+        if !delivered && severity == Severity::Critical {
+            error!("Undelivered critical alert: {}", event);
+        }
+    }
+}
+
+impl SinkSlot {
+    /// Sliding window rate limiting, reports how many alerts were dropped
+    /// once the window frees up
+    fn allow(&mut self, rate_limit: u32, rate_window: Duration) -> bool {
+        if rate_limit == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        while let Some(sent) = self.sent.front() {
+            if now.duration_since(*sent) < rate_window {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= rate_limit as usize {
+            self.suppressed += 1;
+            return false;
+        }
+        self.sent.push_back(now);
+        if self.suppressed > 0 {
+            warn!(
+                "{} alerts to {} suppressed by rate limiting",
+                self.suppressed,
+                self.sink.name()
+            );
+            self.suppressed = 0;
+        }
+        true
+    }
+}
+
+/// Turn cache events into alerts, following configuration reloads.
+/// Sinks block on the network, run this on its own thread
+pub fn alert_handler(
+    mut alerter: Alerter,
+    mut events: EventReceiver,
+    mut config_rx: watch::Receiver<Config>,
+) {
+    loop {
+        let event = match events.blocking_recv() {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("{} events missed by the alerter", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if config_rx.has_changed().unwrap_or(false) {
+            alerter.configure(&config_rx.borrow_and_update().alerts);
+        }
+        alerter.event(&event);
+    }
+}
@@ -0,0 +1,39 @@
+use std::{error::Error, process};
+
+use syslog::Facility;
+
+use super::*;
+
+impl SyslogSink {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let formatter = Formatter3164 {
+            facility: Facility::LOG_DAEMON,
+            hostname: None,
+            process: "arp-watch".to_string(),
+            pid: process::id(),
+        };
+        let logger = syslog::unix(formatter).map_err(|e| format!("syslog: {e}"))?;
+        Ok(Self { logger })
+    }
+}
+
+impl AlertSink for SyslogSink {
+    fn name(&self) -> String {
+        "syslog".to_string()
+    }
+
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        // One record per alert
+        let message = event.to_string().replace('\n', " ");
+        match event.severity() {
+            Severity::Info => self.logger.notice(message),
+            Severity::Critical => self.logger.crit(message),
+        }
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn rate_limited(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,37 @@
+use std::error::Error;
+
+use serde_json::Value;
+
+use super::*;
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(config.timeout()).build();
+        Self { agent, config }
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook {}", self.config.url)
+    }
+
+    /// POST the event as JSON, along with its severity and a readable message
+    fn send(&mut self, event: &ArpEvent) -> Result<(), Box<dyn Error>> {
+        let mut body = serde_json::to_value(event)?;
+        if let Value::Object(fields) = &mut body {
+            fields.insert("severity".into(), serde_json::to_value(event.severity())?);
+            fields.insert("message".into(), event.to_string().into());
+        }
+
+        let mut request = self
+            .agent
+            .post(&self.config.url)
+            .set("Content-Type", "application/json");
+        for (name, value) in self.config.headers.iter() {
+            request = request.set(name, value);
+        }
+        request.send_string(&body.to_string())?;
+        Ok(())
+    }
+}
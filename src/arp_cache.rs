@@ -1,71 +1,909 @@
-use std::{error::Error, fs::File, io::Read, net::Ipv4Addr, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs::{self, File},
+    io::Read,
+    net::{IpAddr, Ipv4Addr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use log::{info, warn};
-use pnet::util::MacAddr;
-use tokio::sync::Mutex;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use pnet::{ipnetwork::IpNetwork, util::MacAddr};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
 
-use crate::alert::alert;
+use crate::{
+    config::{Config, FlappingConfig, MacClaimsConfig, NdpConfig, TrafficConfig},
+    event::{ArpEvent, EventContext, EventReceiver, EventSender, EVENT_CAPACITY},
+    route,
+};
 
-const PATH: &str = "/proc/net/arp";
+pub const PATH: &str = "/proc/net/arp";
+pub const STATE_PATH: &str = "/var/lib/arp-watch/bindings.json";
 
 pub type ArpCacheMutex = Arc<Mutex<ArpCache>>;
 
+/// RFC 5227 DEFEND_INTERVAL, two MACs claiming an IP closer than this are
+/// both alive
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Spoofing tools keep replying, the same sender is reported once per interval
+const UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Routers advertise every few seconds, a rogue one is reported once per
+/// interval
+const ROGUE_ROUTER_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Idle entries of the tracking tables are dropped at most once per interval
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub struct ArpCache {
     vec: Vec<ArpEntry>,
     pub follow_update: bool,
+    events: EventSender,
+    trusted: Vec<TrustedBinding>,
+    state_path: Option<PathBuf>,
+    /// Only keep kernel entries for these interfaces, all when empty
+    devices: Vec<String>,
+    gateways: Vec<Gateway>,
+    probes: Vec<Probe>,
+    /// MAC changes are probed before alerting, never in passive mode
+    verify_changes: bool,
+    /// RFC 5227 probes seen within the last `DEFEND_INTERVAL`
+    acd_probes: Vec<ArpEntry>,
+    flapping: FlappingConfig,
+    /// MAC changes of every (interface, IP), within the flapping window
+    history: HashMap<(String, IpAddr), MacHistory>,
+    mac_claims: MacClaimsConfig,
+    /// IPs claimed by every (interface, MAC), within the claims window
+    claims: HashMap<(String, MacAddr), RecentIps>,
+    /// Last report of unsolicited replies by (interface, IP, MAC)
+    unsolicited: HashMap<(String, IpAddr, MacAddr), DateTime<Utc>>,
+    traffic: TrafficConfig,
+    /// IPs asked for by every (interface, MAC), within the scan window
+    requesters: HashMap<(String, MacAddr), RecentIps>,
+    frame_rates: HashMap<String, FrameRate>,
+    ndp: NdpConfig,
+    /// Router pinned on every interface when no router is configured
+    routers: HashMap<String, MacAddr>,
+    /// Last report of rogue Router Advertisements by (interface, MAC)
+    rogue_routers: HashMap<(String, MacAddr), DateTime<Utc>>,
+    /// Last time idle tracking entries were dropped
+    swept: DateTime<Utc>,
+}
+
+/// Default route next hop of a watched interface
+#[derive(Debug, Clone)]
+struct Gateway {
+    device: String,
+    ip: IpAddr,
+    /// First MAC seen for the gateway, trusted bindings take precedence
+    mac: Option<MacAddr>,
+}
+
+/// Recent MAC changes of a binding
+#[derive(Debug, Clone)]
+struct MacHistory {
+    /// Last MAC seen
+    mac: MacAddr,
+    /// When `mac` was last seen
+    seen: DateTime<Utc>,
+    /// MAC seen before `mac`
+    previous: Option<MacAddr>,
+    /// When the binding moved and to which MAC
+    changes: VecDeque<(DateTime<Utc>, MacAddr)>,
+    flapping: bool,
 }
 
+/// IPs a MAC recently claimed or asked for
+#[derive(Debug, Clone, Default)]
+struct RecentIps {
+    /// Last time each IP was seen
+    ips: HashMap<IpAddr, DateTime<Utc>>,
+    reported: bool,
+}
+
+/// ARP frames of an interface during the current second
+#[derive(Debug, Clone)]
+struct FrameRate {
+    second: DateTime<Utc>,
+    frames: u32,
+    storming: bool,
+}
+
+/// Replies collected while verifying a binding
+#[derive(Debug, Clone)]
+struct Probe {
+    device: String,
+    ip: IpAddr,
+    answers: Vec<MacAddr>,
+    /// MACs that claimed the IP while the probe was in flight
+    merged: Vec<MacAddr>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ArpCacheUpdateResult {
     NewEntry,
     AlreadyExist,
     EntryDiff,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+/// How a binding was learned
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum BindingSource {
+    KernelCache,
+    Reply,
+    Announcement,
+    /// Reply addressed to this host, usually an answer to a scan
+    Scan,
+    /// Sender of an ordinary request
+    Request,
+    /// RFC 5227 probe or IPv6 duplicate address detection, the prober
+    /// holds no address yet
+    Probe,
+    /// Router Advertisement
+    RouterAdvert,
+}
+
+/// Known good binding, from the configuration or an ethers file
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct TrustedBinding {
+    pub ip: IpAddr,
+    pub mac: MacAddr,
+    /// Only pin the IP on this interface, on every interface when missing
+    #[serde(default)]
+    pub interface: Option<String>,
+}
+
+impl TrustedBinding {
+    /// Read an `/etc/ethers` style file, one `MAC IP-or-hostname` per line,
+    /// hostnames are resolved to all their addresses
+    pub fn from_ethers(path: &Path) -> std::result::Result<Vec<Self>, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let mut bindings = vec![];
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let (mac, host) = match (words.next(), words.next()) {
+                (Some(mac), Some(host)) => (mac, host),
+                (None, _) => continue,
+                _ => return Err(format!("{}:{}: missing host", path.display(), number + 1).into()),
+            };
+            let mac: MacAddr = mac
+                .parse()
+                .map_err(|_| format!("{}:{}: invalid MAC {mac}", path.display(), number + 1))?;
+            let ips: Vec<IpAddr> = match host.parse::<IpAddr>() {
+                Ok(ip) => vec![ip],
+                Err(_) => match (host, 0).to_socket_addrs() {
+                    Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+                    Err(e) => {
+                        warn!("{}: cannot resolve {host}: {e}", path.display());
+                        continue;
+                    }
+                },
+            };
+            bindings.extend(ips.into_iter().map(|ip| Self {
+                ip,
+                mac,
+                interface: None,
+            }));
+        }
+        Ok(bindings)
+    }
+
+    /// Whether this pins the IP of `entry`
+    fn pins(&self, entry: &ArpEntry) -> bool {
+        self.ip == entry.ip
+            && match &self.interface {
+                Some(interface) => *interface == entry.device,
+                None => true,
+            }
+    }
+}
+
+/// ATF_* flags of a kernel ARP entry, see `include/uapi/linux/if_arp.h`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub struct ArpFlags(pub u32);
+
+impl ArpFlags {
+    pub const COMPLETE: u32 = 0x02;
+    pub const PERMANENT: u32 = 0x04;
+    pub const PUBLISHED: u32 = 0x08;
+
+    pub fn is_complete(&self) -> bool {
+        self.0 & Self::COMPLETE != 0
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        self.0 & Self::PERMANENT != 0
+    }
+
+    pub fn is_published(&self) -> bool {
+        self.0 & Self::PUBLISHED != 0
+    }
+}
+
+/// ARPHRD_ETHER, the hardware type of every entry learned from the wire
+pub const HW_TYPE_ETHERNET: u16 = 0x1;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ArpEntry {
-    ip: Ipv4Addr,
+    ip: IpAddr,
     mac: MacAddr,
+    hw_type: u16,
+    flags: ArpFlags,
+    mask: Option<Ipv4Addr>,
+    device: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    hits: u64,
+    source: BindingSource,
 }
 
 impl ArpCache {
-    pub fn new(follow_update: bool) -> Self {
-        let mut ret = ArpCache {
+    /// Cache publishing to `events`, subscribe before so that conflicts
+    /// found in the kernel cache are not missed
+    pub fn from_config(config: &Config, devices: Vec<String>, events: EventSender) -> Self {
+        let mut ret = Self::empty(config.follow_update);
+        ret.devices = devices;
+        ret.events = events;
+        ret.verify_changes = !config.passive;
+        ret.configure(config);
+        if let Err(e) = ret.parse(&config.proc_arp_path) {
+            warn!(
+                "Failed to read the kernel ARP cache {}: {e}",
+                config.proc_arp_path.display()
+            );
+        }
+        if let Err(e) = ret.learn_gateways(&config.proc_route_path, &config.proc_route6_path) {
+            warn!("Failed to read the routing tables: {e}");
+        }
+        ret
+    }
+
+    /// Apply the settings that can change while running
+    pub fn configure(&mut self, config: &Config) {
+        self.follow_update = config.follow_update;
+        self.trusted = config.trusted.clone();
+        self.flapping = config.flapping.clone();
+        self.mac_claims = config.mac_claims.clone();
+        self.traffic = config.traffic.clone();
+        self.ndp = config.ndp.clone();
+    }
+
+    /// `None` when nothing is pinned on that IP, otherwise whether the MAC
+    /// is one of the pinned ones
+    fn is_trusted(&self, entry: &ArpEntry) -> Option<bool> {
+        let mut pinned = self
+            .trusted
+            .iter()
+            .filter(|trusted| trusted.pins(entry))
+            .peekable();
+        pinned.peek()?;
+        Some(pinned.any(|trusted| trusted.mac == entry.mac))
+    }
+
+    fn pinned_macs(&self, entry: &ArpEntry) -> Vec<MacAddr> {
+        self.trusted
+            .iter()
+            .filter(|trusted| trusted.pins(entry))
+            .map(|trusted| trusted.mac)
+            .collect()
+    }
+
+    /// Watch the IPv4 and IPv6 default gateways of the watched interfaces,
+    /// pins of the gateways still routed through are kept
+    pub fn learn_gateways(
+        &mut self,
+        path: &Path,
+        path6: &Path,
+    ) -> std::result::Result<(), Box<dyn Error>> {
+        let mut routes: Vec<(String, IpAddr)> = route::default_gateways(path)?
+            .into_iter()
+            .map(|(device, ip)| (device, ip.into()))
+            .collect();
+        routes.extend(
+            route::default_gateways_v6(path6)?
+                .into_iter()
+                .map(|(device, ip)| (device, ip.into())),
+        );
+        let mut gateways = vec![];
+        for (device, ip) in routes {
+            if !self.devices.is_empty() && !self.devices.contains(&device) {
+                continue;
+            }
+            let mac = self
+                .gateways
+                .iter()
+                .find(|gateway| gateway.device == device && gateway.ip == ip)
+                .and_then(|gateway| gateway.mac);
+            info!("Default gateway {} on {}", ip, device);
+            gateways.push(Gateway { device, ip, mac });
+        }
+        self.gateways = gateways;
+        Ok(())
+    }
+
+    /// MACs the gateway behind `entry` is pinned to, `None` when `entry` is
+    /// not a gateway. Pinned to the last known binding, or `entry` itself,
+    /// the first time the gateway is seen
+    fn gateway_macs(&mut self, entry: &ArpEntry) -> Option<Vec<MacAddr>> {
+        let index = self
+            .gateways
+            .iter()
+            .position(|gateway| gateway.device == entry.device && gateway.ip == entry.ip)?;
+        let trusted = self.pinned_macs(entry);
+        if !trusted.is_empty() {
+            return Some(trusted);
+        }
+        if let Some(mac) = self.gateways[index].mac {
+            return Some(vec![mac]);
+        }
+
+        let mac = self
+            .vec
+            .iter()
+            .filter(|known| known.same_key(entry))
+            .max_by_key(|known| known.last_seen)
+            .map_or(entry.mac, |known| known.mac);
+        info!("Gateway {} on {} pinned to {}", entry.ip, entry.device, mac);
+        self.gateways[index].mac = Some(mac);
+        Some(vec![mac])
+    }
+
+    /// Start collecting replies for `ip`, false when already probing it:
+    /// `claimed` then counts as an answer to the probe in flight
+    pub fn start_probe(&mut self, device: &str, ip: IpAddr, claimed: MacAddr) -> bool {
+        if let Some(probe) = self
+            .probes
+            .iter_mut()
+            .find(|probe| probe.device == device && probe.ip == ip)
+        {
+            if !probe.answers.contains(&claimed) {
+                probe.answers.push(claimed);
+                probe.merged.push(claimed);
+            }
+            return false;
+        }
+        self.probes.push(Probe {
+            device: device.to_string(),
+            ip,
+            answers: vec![],
+            merged: vec![],
+        });
+        true
+    }
+
+    /// Stop collecting replies for `ip`, returns the MACs that answered and
+    /// those merged into the probe by `start_probe`
+    pub fn finish_probe(&mut self, device: &str, ip: IpAddr) -> (Vec<MacAddr>, Vec<MacAddr>) {
+        match self
+            .probes
+            .iter()
+            .position(|probe| probe.device == device && probe.ip == ip)
+        {
+            Some(index) => {
+                let probe = self.probes.remove(index);
+                (probe.answers, probe.merged)
+            }
+            None => (vec![], vec![]),
+        }
+    }
+
+    /// Rebind `ip` to `mac` once a probe showed it moved there
+    pub fn migrate(&mut self, device: &str, ip: IpAddr, mac: MacAddr) {
+        self.vec
+            .retain(|entry| entry.device != device || entry.ip != ip || entry.mac == mac);
+        if !self
+            .vec
+            .iter()
+            .any(|entry| entry.device == device && entry.ip == ip)
+        {
+            let entry = ArpEntry::new(ip, mac, BindingSource::Scan, Utc::now()).with_device(device);
+            self.vec.push(entry);
+        }
+        info!("{} on {} moved to {}", ip, device, mac);
+    }
+
+    /// The kernel failed to resolve or dropped `ip` on `device`, its bindings
+    /// are kept but incomplete until the host is seen again
+    pub fn unresolved(&mut self, device: &str, ip: IpAddr) {
+        for entry in self
+            .vec
+            .iter_mut()
+            .filter(|entry| entry.device == device && entry.ip == ip)
+        {
+            entry.flags.0 &= !ArpFlags::COMPLETE;
+        }
+    }
+
+    /// RFC 5227 probe or duplicate address detection for `probe.ip`,
+    /// reported along with the MACs holding
+    /// that IP. A holder answering soon after is defending it
+    pub fn address_probe(&mut self, probe: ArpEntry) {
+        self.acd_probes
+            .retain(|seen| within(seen.last_seen, probe.last_seen, DEFEND_INTERVAL));
+        let holders: Vec<MacAddr> = self
+            .vec
+            .iter()
+            .filter(|entry| entry.same_key(&probe) && entry.mac != probe.mac)
+            .map(|entry| entry.mac)
+            .collect();
+        self.emit(ArpEvent::AddressProbe {
+            context: probe.event_context(),
+            ip: probe.ip,
+            mac: probe.mac,
+            holders,
+        });
+        self.acd_probes
+            .retain(|seen| !seen.same_key(&probe) || seen.mac != probe.mac);
+        self.acd_probes.push(probe);
+    }
+
+    /// Record the MAC of `entry` in its binding history, reports when the
+    /// binding starts flapping. True while it flaps
+    fn track_flapping(&mut self, entry: &ArpEntry) -> bool {
+        let window = self.flapping.window();
+        let limit = self.flapping.changes;
+        let history = self
+            .history
+            .entry((entry.device.clone(), entry.ip))
+            .or_insert_with(|| MacHistory {
+                mac: entry.mac,
+                seen: entry.last_seen,
+                previous: None,
+                changes: VecDeque::new(),
+                flapping: false,
+            });
+        while let Some((changed, _)) = history.changes.front() {
+            if within(*changed, entry.last_seen, window) {
+                break;
+            }
+            history.changes.pop_front();
+        }
+        if history.mac != entry.mac {
+            history.changes.push_back((entry.last_seen, entry.mac));
+            history.previous = Some(history.mac);
+            history.mac = entry.mac;
+            history.seen = entry.last_seen;
+        }
+        history.seen = history.seen.max(entry.last_seen);
+
+        if history.flapping && history.changes.is_empty() {
+            info!(
+                "{} on {} is stable again at {}",
+                entry.ip, entry.device, entry.mac
+            );
+            history.flapping = false;
+        }
+        if limit == 0 || history.flapping || history.changes.len() <= limit {
+            return history.flapping;
+        }
+        history.flapping = true;
+        let mut macs: Vec<MacAddr> = vec![];
+        for (_, mac) in history.changes.iter() {
+            if !macs.contains(mac) {
+                macs.push(*mac);
+            }
+        }
+        let changes = history.changes.len();
+        warn!("{} on {} is flapping", entry.ip, entry.device);
+        self.emit(ArpEvent::Flapping {
+            context: entry.event_context(),
+            ip: entry.ip,
+            macs,
+            changes,
+            window: self.flapping.window,
+        });
+        true
+    }
+
+    /// Drop the tracking entries of bindings and MACs not seen lately
+    fn sweep(&mut self, at: DateTime<Utc>) {
+        if within(self.swept, at, SWEEP_INTERVAL) {
+            return;
+        }
+        self.swept = at;
+        // Conflicts need the history for at least DEFEND_INTERVAL
+        let window = self.flapping.window().max(DEFEND_INTERVAL);
+        self.history
+            .retain(|_, history| within(history.seen, at, window));
+
+        let window = self.mac_claims.window();
+        self.claims.retain(|_, claims| {
+            claims.ips.retain(|_, claimed| within(*claimed, at, window));
+            !claims.ips.is_empty()
+        });
+
+        self.rogue_routers
+            .retain(|_, reported| within(*reported, at, ROGUE_ROUTER_REPORT_INTERVAL));
+
+        let window = self.traffic.scan_window();
+        self.requesters.retain(|_, requester| {
+            requester
+                .ips
+                .retain(|_, requested| within(*requested, at, window));
+            !requester.ips.is_empty()
+        });
+    }
+
+    /// MAC that took the binding over from `entry` and still claims it,
+    /// `entry` being the previous holder coming back: both are alive
+    fn contested(&self, entry: &ArpEntry) -> Option<MacAddr> {
+        let history = self.history.get(&(entry.device.clone(), entry.ip))?;
+        let contested = history.mac != entry.mac
+            && history.previous == Some(entry.mac)
+            && within(history.seen, entry.last_seen, DEFEND_INTERVAL);
+        contested.then_some(history.mac)
+    }
+
+    /// Index `entry` by MAC, reports when the MAC crosses the number of IPs
+    /// it may claim. IPv6 hosts hold several addresses, only IPv4 counts
+    fn track_claims(&mut self, entry: &ArpEntry) {
+        let max_ips = self.mac_claims.max_ips;
+        if max_ips == 0 || self.mac_claims.allow.contains(&entry.mac) || entry.ip.is_ipv6() {
+            return;
+        }
+        let window = self.mac_claims.window();
+        let claims = self
+            .claims
+            .entry((entry.device.clone(), entry.mac))
+            .or_default();
+        claims.ips.insert(entry.ip, entry.last_seen);
+        claims
+            .ips
+            .retain(|_, claimed| within(*claimed, entry.last_seen, window));
+
+        if claims.ips.len() <= max_ips {
+            claims.reported = false;
+            return;
+        }
+        if claims.reported {
+            return;
+        }
+        claims.reported = true;
+        let mut ips: Vec<IpAddr> = claims.ips.keys().copied().collect();
+        ips.sort();
+        error!("{} claims {} IPs on {}", entry.mac, ips.len(), entry.device);
+        self.emit(ArpEvent::MacClaimsIps {
+            context: entry.event_context(),
+            mac: entry.mac,
+            ips,
+        });
+    }
+
+    /// `entry` replied to `target` although nobody asked for its IP
+    pub fn unsolicited_reply(&mut self, entry: &ArpEntry, target: IpAddr) {
+        let key = (entry.device.clone(), entry.ip, entry.mac);
+        if let Some(reported) = self.unsolicited.get(&key) {
+            if within(*reported, entry.last_seen, UNSOLICITED_REPORT_INTERVAL) {
+                debug!("Unsolicited reply from {} again", entry.mac);
+                return;
+            }
+        }
+        self.unsolicited
+            .retain(|_, reported| within(*reported, entry.last_seen, UNSOLICITED_REPORT_INTERVAL));
+        self.unsolicited.insert(key, entry.last_seen);
+        warn!("Unsolicited reply for {} from {}", entry.ip, entry.mac);
+        self.emit(ArpEvent::UnsolicitedReply {
+            context: entry.event_context(),
+            ip: entry.ip,
+            mac: entry.mac,
+            target,
+        });
+    }
+
+    /// `mac` asked who holds `target`, reports when it asked for too many
+    /// distinct IPs lately
+    pub fn track_request(&mut self, device: &str, mac: MacAddr, target: IpAddr, at: DateTime<Utc>) {
+        let max_targets = self.traffic.scan_targets;
+        if max_targets == 0 {
+            return;
+        }
+        let window = self.traffic.scan_window();
+        let requester = self
+            .requesters
+            .entry((device.to_string(), mac))
+            .or_default();
+        requester.ips.insert(target, at);
+        requester
+            .ips
+            .retain(|_, requested| within(*requested, at, window));
+
+        if requester.ips.len() <= max_targets {
+            requester.reported = false;
+            return;
+        }
+        if requester.reported {
+            return;
+        }
+        requester.reported = true;
+        let targets = requester.ips.len();
+        warn!("{} is scanning {}", mac, device);
+        self.emit(ArpEvent::ArpScan {
+            context: EventContext::new(at, device, Some(BindingSource::Request)),
+            mac,
+            targets,
+            window: self.traffic.scan_window,
+        });
+    }
+
+    /// Count an ARP frame seen on `device`, reports when a storm starts,
+    /// with the frames of the first second over the limit
+    pub fn track_frame(&mut self, device: &str, at: DateTime<Utc>) {
+        let storm_pps = self.traffic.storm_pps;
+        if storm_pps == 0 {
+            return;
+        }
+        let rate = self
+            .frame_rates
+            .entry(device.to_string())
+            .or_insert_with(|| FrameRate {
+                second: at,
+                frames: 0,
+                storming: false,
+            });
+        if within(rate.second, at, Duration::from_secs(1)) {
+            rate.frames += 1;
+            return;
+        }
+        let frames = rate.frames;
+        rate.second = at;
+        rate.frames = 1;
+        if rate.storming && frames <= storm_pps {
+            info!("ARP storm on {} is over", device);
+            rate.storming = false;
+        }
+        if rate.storming || frames <= storm_pps {
+            return;
+        }
+        rate.storming = true;
+        warn!("ARP storm on {}", device);
+        self.emit(ArpEvent::ArpStorm {
+            context: EventContext::new(at, device, None),
+            pps: frames,
+        });
+    }
+
+    /// Router Advertisement from `entry`, reported when the MAC is neither
+    /// an allowed router nor the one pinned on the interface
+    pub fn router_advert(&mut self, entry: &ArpEntry, lifetime: u16, prefixes: Vec<IpNetwork>) {
+        let expected = match self.ndp.routers.is_empty() {
+            false => self.ndp.routers.clone(),
+            true => match self.routers.get(&entry.device) {
+                Some(mac) => vec![*mac],
+                None => match self.pin_router(entry) {
+                    Some(mac) => vec![mac],
+                    None => return,
+                },
+            },
+        };
+        if expected.contains(&entry.mac) {
+            return;
+        }
+
+        let key = (entry.device.clone(), entry.mac);
+        if let Some(reported) = self.rogue_routers.get(&key) {
+            if within(*reported, entry.last_seen, ROGUE_ROUTER_REPORT_INTERVAL) {
+                debug!("Router Advertisement from {} again", entry.mac);
+                return;
+            }
+        }
+        self.rogue_routers.insert(key, entry.last_seen);
+        error!("Rogue router {} on {}", entry.mac, entry.device);
+        self.emit(ArpEvent::RogueRouter {
+            context: entry.event_context(),
+            ip: entry.ip,
+            mac: entry.mac,
+            expected,
+            lifetime,
+            prefixes,
+        });
+    }
+
+    /// Pin the router of `entry` interface: the next hop of the IPv6
+    /// default route, or `entry` when there is no such route. `None` while
+    /// the default router has not been heard from
+    fn pin_router(&mut self, entry: &ArpEntry) -> Option<MacAddr> {
+        let gateway = self
+            .gateways
+            .iter()
+            .find(|gateway| gateway.device == entry.device && gateway.ip.is_ipv6());
+        let (ip, mac) = match gateway {
+            Some(gateway) => {
+                let known = gateway.mac.or_else(|| {
+                    self.vec
+                        .iter()
+                        .filter(|known| known.device == entry.device && known.ip == gateway.ip)
+                        .max_by_key(|known| known.last_seen)
+                        .map(|known| known.mac)
+                });
+                match known {
+                    Some(mac) => (gateway.ip, mac),
+                    None if gateway.ip == entry.ip => (entry.ip, entry.mac),
+                    None => {
+                        debug!(
+                            "Router Advertisement from {} before the default router {}",
+                            entry.ip, gateway.ip
+                        );
+                        return None;
+                    }
+                }
+            }
+            None => (entry.ip, entry.mac),
+        };
+
+        info!("Router {} on {} pinned to {}", ip, entry.device, mac);
+        self.routers.insert(entry.device.clone(), mac);
+        self.emit(ArpEvent::RouterPinned {
+            context: entry.event_context(),
+            ip,
+            mac,
+            from_route: gateway.is_some(),
+        });
+        Some(mac)
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    /// Handle to publish events alongside the cache, e.g. from the scanner
+    pub fn events(&self) -> EventSender {
+        self.events.clone()
+    }
+
+    pub fn emit(&self, event: ArpEvent) {
+        // Nobody listening is not an error
+        let _ = self.events.send(event);
+    }
+
+    fn trusted_violation(&self, entry: &ArpEntry) -> ArpEvent {
+        ArpEvent::TrustedViolation {
+            context: entry.event_context(),
+            ip: entry.ip,
+            expected: self.pinned_macs(entry),
+            actual: entry.mac,
+        }
+    }
+
+    /// Cache that ignores the kernel ARP table, used when replaying captures
+    pub fn empty(follow_update: bool) -> Self {
+        ArpCache {
             vec: vec![],
             follow_update,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            trusted: vec![],
+            state_path: None,
+            devices: vec![],
+            gateways: vec![],
+            probes: vec![],
+            verify_changes: false,
+            acd_probes: vec![],
+            flapping: FlappingConfig::default(),
+            history: HashMap::new(),
+            mac_claims: MacClaimsConfig::default(),
+            claims: HashMap::new(),
+            unsolicited: HashMap::new(),
+            traffic: TrafficConfig::default(),
+            requesters: HashMap::new(),
+            frame_rates: HashMap::new(),
+            ndp: NdpConfig::default(),
+            routers: HashMap::new(),
+            rogue_routers: HashMap::new(),
+            swept: DateTime::<Utc>::UNIX_EPOCH,
+        }
+    }
+
+    /// Merge the bindings saved by a previous run so that known hosts are
+    /// not reported as new, and remember where to save them back. A corrupt
+    /// database is moved aside
+    pub fn load_state(&mut self, path: &Path) -> std::result::Result<usize, Box<dyn Error>> {
+        self.state_path = Some(path.to_owned());
+        if !path.exists() {
+            info!("No binding database at {}, starting fresh", path.display());
+            return Ok(0);
+        }
+
+        let saved: Vec<ArpEntry> = match serde_json::from_str(&fs::read_to_string(path)?) {
+            Ok(saved) => saved,
+            Err(e) => {
+                // Saving would overwrite it otherwise
+                let aside = path.with_extension("json.corrupt");
+                fs::rename(path, &aside)?;
+                return Err(format!("{e}, moved to {}", aside.display()).into());
+            }
         };
-        ret.parse().unwrap();
-        ret
+        let count = saved.len();
+        for saved_entry in saved {
+            if !self.in_scope(&saved_entry) {
+                continue;
+            }
+            if self.is_trusted(&saved_entry) == Some(false) {
+                warn!(
+                    "Dropping saved binding {} at {}, not the trusted MAC",
+                    saved_entry.ip, saved_entry.mac
+                );
+                continue;
+            }
+            match self
+                .vec
+                .iter_mut()
+                .find(|entry| entry.same_key(&saved_entry) && entry.mac == saved_entry.mac)
+            {
+                // Already learned from the kernel cache, keep the history
+                Some(entry) => {
+                    entry.first_seen = saved_entry.first_seen;
+                    entry.hits += saved_entry.hits;
+                    entry.source = saved_entry.source;
+                }
+                None => self.vec.push(saved_entry),
+            }
+        }
+        info!("Loaded {} bindings from {}", count, path.display());
+        Ok(count)
     }
 
-    pub fn parse(&mut self) -> std::result::Result<u8, Box<dyn Error>> {
-        let mut entry_count: u8 = 0;
+    /// Write the bindings to the state file given to `load_state`, if any
+    pub fn save_state(&self) -> std::result::Result<(), Box<dyn Error>> {
+        let path = match &self.state_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write then rename so a crash never leaves a truncated database
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.vec)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn in_scope(&self, entry: &ArpEntry) -> bool {
+        self.devices.is_empty() || self.devices.contains(&entry.device)
+    }
+
+    pub fn parse(&mut self, path: &Path) -> std::result::Result<usize, Box<dyn Error>> {
+        let mut entry_count = 0;
 
-        let path = Path::new(PATH);
         let mut file = File::open(path)?;
 
         let mut file_content = String::new();
-        file.read_to_string(&mut file_content).unwrap();
+        file.read_to_string(&mut file_content)?;
         for line in file_content.lines() {
-            let mut words = line.split_whitespace();
-            let ip_str = match words.next() {
-                Some(addr) => {
-                    if addr == "IP" {
-                        continue;
-                    }
-                    addr
+            if line.starts_with("IP") || line.trim().is_empty() {
+                continue;
+            }
+            let new_entry = match ArpEntry::from_proc_line(line) {
+                Some(entry) => entry,
+                None => {
+                    warn!("Unparsable ARP cache line: {}", line);
+                    continue;
                 }
-                None => continue,
             };
-            words.next();
-            words.next();
-            // TODO parse the last collumn for the mask
-            let mac_str = words.next().unwrap();
+            if !self.in_scope(&new_entry) {
+                continue;
+            }
+            // Pending resolutions, nobody answered for that IP (yet)
+            if !new_entry.flags.is_complete() || new_entry.mac == MacAddr::zero() {
+                info!("Skipping incomplete ARP entry for {}", new_entry.ip);
+                continue;
+            }
+            if self.is_trusted(&new_entry) == Some(false) {
+                warn!("Kernel cache contradicts a trusted binding");
+                self.emit(self.trusted_violation(&new_entry));
+                continue;
+            }
             info!(
-                "Making new ARP Entry from existing cache: {} {}",
-                ip_str, mac_str
+                "Making new ARP Entry from existing cache: {} {} on {}",
+                new_entry.ip, new_entry.mac, new_entry.device
             );
-            let new_entry = ArpEntry::from(ip_str, mac_str);
 
             self.vec.push(new_entry);
             entry_count += 1;
@@ -73,58 +911,814 @@ impl ArpCache {
         Ok(entry_count)
     }
 
+    /// Record a binding seen on the wire or in the kernel. Contradicting a
+    /// trusted binding is critical and never recorded, other changes are
+    /// informational. Bindings are keyed by interface and IP, the same
+    /// address may legitimately live on several VLANs
     pub fn update(&mut self, new_entry: ArpEntry) -> ArpCacheUpdateResult {
-        let mut entry_diff = false;
-        for entry in self.vec.iter() {
-            if new_entry.ip == entry.ip && new_entry.mac == entry.mac {
-                warn!("Entry already exist");
+        self.sweep(new_entry.last_seen);
+
+        // Replies addressed to this host answer pending probes
+        if new_entry.source == BindingSource::Scan {
+            for probe in self.probes.iter_mut() {
+                if probe.device == new_entry.device
+                    && probe.ip == new_entry.ip
+                    && !probe.answers.contains(&new_entry.mac)
+                {
+                    probe.answers.push(new_entry.mac);
+                }
+            }
+        }
+
+        // Another MAC claiming a probed IP defends it, the prober itself
+        // claiming it means nobody did
+        let probers: Vec<MacAddr> = self
+            .acd_probes
+            .iter()
+            .filter(|probe| probe.same_key(&new_entry) && probe.mac != new_entry.mac)
+            .filter(|probe| within(probe.last_seen, new_entry.last_seen, DEFEND_INTERVAL))
+            .map(|probe| probe.mac)
+            .collect();
+        for prober in probers {
+            self.emit(ArpEvent::AddressDefended {
+                context: new_entry.event_context(),
+                ip: new_entry.ip,
+                mac: new_entry.mac,
+                prober,
+            });
+        }
+        self.acd_probes.retain(|probe| !probe.same_key(&new_entry));
+        self.track_claims(&new_entry);
+
+        if new_entry.source == BindingSource::Announcement {
+            self.emit(ArpEvent::GratuitousArp {
+                context: new_entry.event_context(),
+                ip: new_entry.ip,
+                mac: new_entry.mac,
+            });
+        }
+
+        let known = self.vec.iter().any(|entry| entry.same_key(&new_entry));
+        if let Some(expected) = self.gateway_macs(&new_entry) {
+            if !expected.contains(&new_entry.mac) {
+                error!("Gateway binding contradicted");
+                self.emit(ArpEvent::GatewayChanged {
+                    context: new_entry.event_context(),
+                    ip: new_entry.ip,
+                    expected,
+                    actual: new_entry.mac,
+                });
+                return match known {
+                    true => ArpCacheUpdateResult::EntryDiff,
+                    false => ArpCacheUpdateResult::NewEntry,
+                };
+            }
+        }
+
+        let trusted = self.is_trusted(&new_entry);
+        if trusted == Some(false) {
+            error!("Trusted binding violated");
+            self.emit(self.trusted_violation(&new_entry));
+            return match known {
+                true => ArpCacheUpdateResult::EntryDiff,
+                false => ArpCacheUpdateResult::NewEntry,
+            };
+        }
+
+        // A move only turns into a conflict once the previous holder claims
+        // the IP again, until then the prober may confirm the move
+        let contested = self.contested(&new_entry);
+        let flapping = self.track_flapping(&new_entry);
+        if let (Some(holder), false) = (contested, flapping) {
+            error!("Duplicate IP {}", new_entry.ip);
+            self.emit(ArpEvent::DuplicateIp {
+                context: new_entry.event_context(),
+                ip: new_entry.ip,
+                macs: vec![holder, new_entry.mac],
+            });
+        }
+
+        let mut previous = vec![];
+        for entry in self.vec.iter_mut() {
+            if entry.same_key(&new_entry) && new_entry.mac == entry.mac {
+                debug!("Entry already exist");
+                entry.last_seen = entry.last_seen.max(new_entry.last_seen);
+                entry.hits += 1;
+                entry.flags.0 |= new_entry.flags.0 & ArpFlags::COMPLETE;
                 return ArpCacheUpdateResult::AlreadyExist;
             }
-            if entry.ip == new_entry.ip && entry.mac != new_entry.mac {
+            if entry.same_key(&new_entry) && entry.mac != new_entry.mac {
                 warn!("Entry divergence spotted");
-                entry_diff = true;
-                alert(format!(
-                    "[{}]\nwas {}, now {}",
-                    entry.ip, entry.mac, new_entry.mac
-                ));
+                previous.push(entry.mac);
             }
         }
 
-        if !entry_diff {
-            alert(format!("{} at {}", new_entry.ip, new_entry.mac));
-            self.vec.push(new_entry);
+        if flapping {
+            debug!("Change on a flapping binding, already reported");
+        } else if contested.is_some() {
+            debug!("Change on a contested binding, already reported");
+        } else {
+            // Only ARP bindings can be probed
+            let verifying = self.verify_changes && new_entry.ip.is_ipv4();
+            for old in previous.iter() {
+                self.emit(ArpEvent::MacChanged {
+                    context: new_entry.event_context(),
+                    ip: new_entry.ip,
+                    old: *old,
+                    new: new_entry.mac,
+                    verifying,
+                });
+            }
+        }
+
+        if previous.is_empty() {
+            if trusted == Some(true) {
+                info!("Trusted binding {} at {}", new_entry.ip, new_entry.mac);
+            }
+            self.emit(ArpEvent::NewHost {
+                context: new_entry.event_context(),
+                ip: new_entry.ip,
+                mac: new_entry.mac,
+                trusted: trusted == Some(true),
+            });
+            self.vec.push(new_entry.clone());
             warn!("New entry registered");
 
             return ArpCacheUpdateResult::NewEntry;
         }
 
-        if self.follow_update {
+        // Back to the trusted MAC forgetting the impostors, or following the
+        // change. Earlier MACs are kept in the history
+        if trusted == Some(true) || self.follow_update {
+            self.vec.retain(|entry| !entry.same_key(&new_entry));
             self.vec.push(new_entry);
         }
         ArpCacheUpdateResult::EntryDiff
     }
 
+    /// Take the bindings of another cache, e.g. the capture daemon one
+    pub fn replace_entries(&mut self, entries: Vec<ArpEntry>) {
+        self.vec = entries;
+    }
+
     pub fn entries(&self) -> Vec<ArpEntry> {
         self.vec.clone()
     }
 }
 
+/// Whether `later` comes less than `window` after `earlier`, clocks going
+/// backwards count as simultaneous
+pub(crate) fn within(earlier: DateTime<Utc>, later: DateTime<Utc>, window: Duration) -> bool {
+    (later - earlier)
+        .to_std()
+        .map_or(true, |elapsed| elapsed < window)
+}
+
 impl ArpEntry {
-    pub fn new(ip: Ipv4Addr, mac: MacAddr) -> Self {
-        Self { ip, mac }
+    pub fn new(ip: IpAddr, mac: MacAddr, source: BindingSource, seen: DateTime<Utc>) -> Self {
+        Self {
+            ip,
+            mac,
+            hw_type: HW_TYPE_ETHERNET,
+            flags: ArpFlags(ArpFlags::COMPLETE),
+            mask: None,
+            device: String::new(),
+            first_seen: seen,
+            last_seen: seen,
+            hits: 1,
+            source,
+        }
+    }
+
+    /// Parse a `/proc/net/arp` line:
+    /// `IP address  HW type  Flags  HW address  Mask  Device`
+    pub fn from_proc_line(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let ip: IpAddr = IpAddr::V4(words.next()?.parse().ok()?);
+        let hw_type = u16::from_str_radix(words.next()?.trim_start_matches("0x"), 16).ok()?;
+        let flags = u32::from_str_radix(words.next()?.trim_start_matches("0x"), 16).ok()?;
+        let mac: MacAddr = words.next()?.parse().ok()?;
+        let mask = match words.next()? {
+            "*" => None,
+            mask => Some(mask.parse().ok()?),
+        };
+        let device = words.next()?.to_string();
+
+        let mut entry = Self::new(ip, mac, BindingSource::KernelCache, Utc::now());
+        entry.hw_type = hw_type;
+        entry.flags = ArpFlags(flags);
+        entry.mask = mask;
+        entry.device = device;
+        Some(entry)
     }
 
-    pub fn from(ip_str: &str, mac_str: &str) -> Self {
-        let ip: Ipv4Addr = ip_str.parse().unwrap();
-        let mac: MacAddr = mac_str.parse().unwrap();
-        Self { ip, mac }
+    /// Same interface and IP
+    pub fn same_key(&self, other: &ArpEntry) -> bool {
+        self.device == other.device && self.ip == other.ip
     }
 
-    pub fn ip(&self) -> &Ipv4Addr {
+    pub fn event_context(&self) -> EventContext {
+        EventContext::new(self.last_seen, &self.device, Some(self.source))
+    }
+
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.device = device.to_string();
+        self
+    }
+
+    pub fn with_flags(mut self, flags: ArpFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn ip(&self) -> &IpAddr {
         &self.ip
     }
 
     pub fn mac(&self) -> &MacAddr {
         &self.mac
     }
+
+    pub fn hw_type(&self) -> u16 {
+        self.hw_type
+    }
+
+    pub fn flags(&self) -> ArpFlags {
+        self.flags
+    }
+
+    pub fn mask(&self) -> Option<&Ipv4Addr> {
+        self.mask.as_ref()
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn first_seen(&self) -> &DateTime<Utc> {
+        &self.first_seen
+    }
+
+    pub fn last_seen(&self) -> &DateTime<Utc> {
+        &self.last_seen
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn source(&self) -> BindingSource {
+        self.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{alert::Severity, test_util::*};
+
+    use super::*;
+
+    const DEVICE: &str = "eth0";
+
+    fn entry(ip: [u8; 4], mac: u8, source: BindingSource, secs: i64) -> ArpEntry {
+        ArpEntry::new(Ipv4Addr::from(ip).into(), mac_addr(mac), source, at(secs))
+            .with_device(DEVICE)
+    }
+
+    #[test]
+    fn move_is_not_a_duplicate() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Announcement, 1));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 2));
+        assert_eq!(
+            event_names(&mut events),
+            ["NewHost", "GratuitousArp", "MacChanged", "MacChanged"]
+        );
+    }
+
+    #[test]
+    fn previous_holder_back_is_a_duplicate() {
+        let mut cache = ArpCache::empty(true);
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 1));
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 2));
+        assert_eq!(
+            event_names(&mut events),
+            ["NewHost", "MacChanged", "DuplicateIp"]
+        );
+    }
+
+    #[test]
+    fn previous_holder_back_later_is_a_move() {
+        let mut cache = ArpCache::empty(true);
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 1));
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 30));
+        assert_eq!(
+            event_names(&mut events),
+            ["NewHost", "MacChanged", "MacChanged"]
+        );
+    }
+
+    #[test]
+    fn flapping() {
+        let mut cache = ArpCache::empty(true);
+        let mut events = cache.subscribe();
+        for (at, mac) in [(0, 1), (20, 2), (40, 1), (45, 2), (50, 1), (55, 2)] {
+            cache.update(entry([10, 0, 0, 2], mac, BindingSource::Reply, at));
+        }
+        assert_eq!(
+            event_names(&mut events),
+            [
+                "NewHost",
+                "MacChanged",
+                "MacChanged",
+                "DuplicateIp",
+                "Flapping"
+            ]
+        );
+
+        // Quiet for a window, changes are news again
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 200));
+        assert_eq!(event_names(&mut events), ["MacChanged"]);
+    }
+
+    #[test]
+    fn idle_history_swept() {
+        let mut cache = ArpCache::empty(false);
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 3], 2, BindingSource::Reply, 30));
+        assert_eq!(cache.history.len(), 2);
+        cache.update(entry([10, 0, 0, 3], 2, BindingSource::Reply, 80));
+        assert_eq!(cache.history.len(), 1);
+    }
+
+    #[test]
+    fn mac_claims() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        for last in 1..=6 {
+            cache.update(entry(
+                [10, 0, 0, last],
+                9,
+                BindingSource::Reply,
+                last as i64,
+            ));
+        }
+        let names = event_names(&mut events);
+        assert_eq!(
+            names.iter().filter(|name| **name == "MacClaimsIps").count(),
+            1
+        );
+        assert_eq!(names[4], "MacClaimsIps");
+
+        // Claims age out of the window
+        cache.update(entry([10, 0, 0, 7], 9, BindingSource::Reply, 400));
+        assert_eq!(cache.claims.len(), 1);
+        assert_eq!(cache.claims.values().next().unwrap().ips.len(), 1);
+    }
+
+    #[test]
+    fn mac_claims_allowed() {
+        let mut cache = ArpCache::empty(false);
+        cache.mac_claims.allow.push(mac_addr(9));
+        let mut events = cache.subscribe();
+        for last in 1..=6 {
+            cache.update(entry([10, 0, 0, last], 9, BindingSource::Reply, 0));
+        }
+        assert!(!event_names(&mut events).contains(&"MacClaimsIps"));
+    }
+
+    #[test]
+    fn arp_scan() {
+        let mut cache = ArpCache::empty(false);
+        cache.traffic.scan_targets = 3;
+        let mut events = cache.subscribe();
+        for last in 1..=5 {
+            cache.track_request(DEVICE, mac_addr(1), [10, 0, 0, last].into(), at(0));
+        }
+        cache.track_request(DEVICE, mac_addr(2), [10, 0, 0, 1].into(), at(0));
+        assert_eq!(event_names(&mut events), ["ArpScan"]);
+
+        // Idle requesters are swept along with the bindings
+        cache.update(entry([10, 0, 0, 9], 9, BindingSource::Reply, 100));
+        assert!(cache.requesters.is_empty());
+    }
+
+    #[test]
+    fn arp_storm() {
+        let mut cache = ArpCache::empty(false);
+        cache.traffic.storm_pps = 10;
+        let mut events = cache.subscribe();
+        for frame in 0..30 {
+            cache.track_frame(DEVICE, at_millis(frame * 10));
+        }
+        cache.track_frame(DEVICE, at(1));
+        cache.track_frame(DEVICE, at(2));
+        cache.track_frame(DEVICE, at(3));
+        let mut storms = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let ArpEvent::ArpStorm { pps, .. } = event {
+                storms.push(pps);
+            }
+        }
+        assert_eq!(storms, [30]);
+        assert!(!cache.frame_rates[DEVICE].storming);
+    }
+
+    #[test]
+    fn address_probe_defended() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 0));
+        cache.address_probe(entry([10, 0, 0, 2], 2, BindingSource::Probe, 10));
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Announcement, 11));
+        let names = event_names(&mut events);
+        assert_eq!(names[1..3], ["AddressProbe", "AddressDefended"]);
+    }
+
+    #[test]
+    fn address_probe_undefended() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        cache.address_probe(entry([10, 0, 0, 2], 2, BindingSource::Probe, 0));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Announcement, 2));
+        cache.update(entry([10, 0, 0, 2], 1, BindingSource::Reply, 30));
+        let names = event_names(&mut events);
+        assert_eq!(names[0], "AddressProbe");
+        assert!(!names.contains(&"AddressDefended"));
+    }
+
+    #[test]
+    fn conflicts_merge_into_probe_in_flight() {
+        let mut cache = ArpCache::empty(false);
+        let ip = IpAddr::from([10, 0, 0, 2]);
+        assert!(cache.start_probe(DEVICE, ip, mac_addr(2)));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Scan, 0));
+        assert!(!cache.start_probe(DEVICE, ip, mac_addr(2)));
+        assert!(!cache.start_probe(DEVICE, ip, mac_addr(3)));
+
+        let (answers, merged) = cache.finish_probe(DEVICE, ip);
+        assert_eq!(answers, [mac_addr(2), mac_addr(3)]);
+        assert_eq!(merged, [mac_addr(3)]);
+        assert!(cache.start_probe(DEVICE, ip, mac_addr(3)));
+    }
+
+    #[test]
+    fn unresolved_until_seen_again() {
+        let mut cache = ArpCache::empty(false);
+        let host = entry([10, 0, 0, 2], 1, BindingSource::KernelCache, 0);
+        cache.update(host.clone());
+
+        cache.unresolved(DEVICE, *host.ip());
+        assert!(!cache.entries()[0].flags().is_complete());
+
+        let again = entry([10, 0, 0, 2], 1, BindingSource::Reply, 5);
+        assert_eq!(cache.update(again), ArpCacheUpdateResult::AlreadyExist);
+        assert!(cache.entries()[0].flags().is_complete());
+    }
+
+    fn router(ip: &str, mac: u8, secs: i64) -> ArpEntry {
+        let ip: std::net::Ipv6Addr = ip.parse().unwrap();
+        ArpEntry::new(
+            ip.into(),
+            mac_addr(mac),
+            BindingSource::RouterAdvert,
+            at(secs),
+        )
+        .with_device(DEVICE)
+    }
+
+    #[test]
+    fn first_router_pinned() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        cache.router_advert(&router("fe80::1", 1, 0), 1800, vec![]);
+        cache.router_advert(&router("fe80::1", 1, 1), 1800, vec![]);
+        cache.router_advert(&router("fe80::2", 2, 2), 1800, vec![]);
+        cache.router_advert(&router("fe80::2", 2, 3), 1800, vec![]);
+        assert_eq!(event_names(&mut events), ["RouterPinned", "RogueRouter"]);
+
+        cache.sweep(at(7200));
+        assert!(cache.rogue_routers.is_empty());
+    }
+
+    #[test]
+    fn default_router_pinned() {
+        let mut cache = ArpCache::empty(false);
+        let mut events = cache.subscribe();
+        cache.gateways = vec![Gateway {
+            device: DEVICE.into(),
+            ip: "fe80::1".parse().unwrap(),
+            mac: None,
+        }];
+        cache.router_advert(&router("fe80::2", 2, 0), 1800, vec![]);
+        assert_eq!(event_names(&mut events), [] as [&str; 0]);
+
+        cache.router_advert(&router("fe80::1", 1, 1), 1800, vec![]);
+        cache.router_advert(&router("fe80::2", 2, 2), 1800, vec![]);
+        let mut names = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let ArpEvent::RouterPinned { from_route, .. } = event {
+                assert!(from_route);
+            }
+            names.push(event.name());
+        }
+        assert_eq!(names, ["RouterPinned", "RogueRouter"]);
+    }
+
+    #[test]
+    fn default_router_known_before_advertising() {
+        let mut cache = ArpCache::empty(false);
+        cache.gateways = vec![Gateway {
+            device: DEVICE.into(),
+            ip: "fe80::1".parse().unwrap(),
+            mac: None,
+        }];
+        cache.update(router("fe80::1", 1, 0));
+        let mut events = cache.subscribe();
+        cache.router_advert(&router("fe80::2", 2, 1), 1800, vec![]);
+        assert_eq!(event_names(&mut events), ["RouterPinned", "RogueRouter"]);
+        assert_eq!(cache.routers.get(DEVICE), Some(&mac_addr(1)));
+    }
+
+    #[test]
+    fn configured_routers() {
+        let mut cache = ArpCache::empty(false);
+        cache.ndp.routers = vec![mac_addr(2)];
+        let mut events = cache.subscribe();
+        cache.router_advert(&router("fe80::2", 2, 0), 1800, vec![]);
+        cache.router_advert(&router("fe80::1", 1, 1), 1800, vec![]);
+        assert_eq!(event_names(&mut events), ["RogueRouter"]);
+        assert!(cache.routers.is_empty());
+    }
+
+    #[test]
+    fn proc_lines() {
+        let entry = ArpEntry::from_proc_line(
+            "192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *        eth0",
+        )
+        .unwrap();
+        assert_eq!(*entry.ip(), IpAddr::from([192, 168, 1, 1]));
+        assert_eq!(
+            *entry.mac(),
+            MacAddr::new(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff)
+        );
+        assert_eq!(entry.hw_type(), HW_TYPE_ETHERNET);
+        assert!(entry.flags().is_complete());
+        assert!(!entry.flags().is_permanent());
+        assert_eq!(entry.device(), "eth0");
+
+        let published = ArpEntry::from_proc_line(
+            "10.0.0.7         0x1         0xe         02:00:00:00:00:07     255.255.255.0 eth1",
+        )
+        .unwrap();
+        assert!(published.flags().is_permanent() && published.flags().is_published());
+        assert_eq!(published.mask(), Some(&Ipv4Addr::new(255, 255, 255, 0)));
+
+        for line in [
+            "192.168.1.1      0x1         0x2",
+            "192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *",
+            "192.168.1.1      0xzz        0x2         aa:bb:cc:dd:ee:ff     *        eth0",
+            "192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee        *        eth0",
+            "fe80::1          0x1         0x2         aa:bb:cc:dd:ee:ff     *        eth0",
+        ] {
+            assert_eq!(ArpEntry::from_proc_line(line), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn proc_file() {
+        let file = TempFile::new(
+            "arp",
+            "IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:01     *        eth0
+192.168.1.50     0x1         0x0         00:00:00:00:00:00     *        eth0
+192.168.2.1      0x1         0x2         aa:bb:cc:dd:ee:02     *        eth1
+192.168.1.9      0x1         0x2
+192.168.1.2      0x1         0x6         aa:bb:cc:dd:ee:03     *        eth0
+",
+        );
+        let mut cache = ArpCache::empty(false);
+        cache.devices = vec![DEVICE.into()];
+        assert_eq!(cache.parse(&file.path).unwrap(), 2);
+        let ips: Vec<IpAddr> = cache.entries().iter().map(|entry| *entry.ip()).collect();
+        assert_eq!(
+            ips,
+            [IpAddr::from([192, 168, 1, 1]), [192, 168, 1, 2].into()]
+        );
+        assert!(cache.entries()[1].flags().is_permanent());
+        assert_eq!(cache.entries()[0].source(), BindingSource::KernelCache);
+
+        assert!(cache.parse(Path::new("/nonexistent/arp")).is_err());
+    }
+
+    #[test]
+    fn ethers_file() {
+        let file = TempFile::new(
+            "ethers",
+            "# gateway
+02:00:00:00:00:01 10.0.0.1   # trailing comment
+
+02:00:00:00:00:02\tlocalhost
+02:00:00:00:00:03 no-such-host.invalid
+",
+        );
+        let bindings = TrustedBinding::from_ethers(&file.path).unwrap();
+        assert_eq!(
+            bindings[0],
+            TrustedBinding {
+                ip: [10, 0, 0, 1].into(),
+                mac: mac_addr(1),
+                interface: None,
+            }
+        );
+        // Hostnames resolve to every address, unresolvable ones are skipped
+        assert!(bindings.len() > 1);
+        assert!(bindings[1..]
+            .iter()
+            .all(|binding| binding.mac == mac_addr(2) && binding.ip.is_loopback()));
+
+        let file = TempFile::new("bad-mac", "10.0.0.1 02:00:00:00:00:01\n");
+        let error = TrustedBinding::from_ethers(&file.path).unwrap_err();
+        assert!(
+            error.to_string().ends_with(":1: invalid MAC 10.0.0.1"),
+            "{error}"
+        );
+        let file = TempFile::new("missing-host", "# hosts\n02:00:00:00:00:01\n");
+        let error = TrustedBinding::from_ethers(&file.path).unwrap_err();
+        assert!(error.to_string().ends_with(":2: missing host"), "{error}");
+    }
+
+    #[test]
+    fn trusted_violation_is_critical() {
+        let mut cache = ArpCache::empty(false);
+        cache.trusted = vec![TrustedBinding {
+            ip: [10, 0, 0, 1].into(),
+            mac: mac_addr(1),
+            interface: None,
+        }];
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 1], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 1));
+        cache.update(entry([10, 0, 0, 2], 3, BindingSource::Reply, 2));
+        cache.update(entry([10, 0, 0, 2], 4, BindingSource::Reply, 3));
+
+        let mut severities = vec![];
+        while let Ok(event) = events.try_recv() {
+            severities.push((event.name(), event.severity()));
+        }
+        assert_eq!(
+            severities,
+            [
+                ("NewHost", Severity::Info),
+                ("TrustedViolation", Severity::Critical),
+                ("NewHost", Severity::Info),
+                ("MacChanged", Severity::Info),
+            ]
+        );
+        // The pinned binding is kept
+        assert_eq!(*cache.entries()[0].mac(), mac_addr(1));
+    }
+
+    #[test]
+    fn trusted_binding_on_another_interface() {
+        let mut cache = ArpCache::empty(false);
+        cache.trusted = vec![TrustedBinding {
+            ip: [10, 0, 0, 1].into(),
+            mac: mac_addr(1),
+            interface: Some("eth1".into()),
+        }];
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 0));
+        assert_eq!(event_names(&mut events), ["NewHost"]);
+    }
+
+    const ROUTES: &str =
+        "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+";
+
+    /// Cache routing through 10.0.0.1 on eth0
+    fn gateway_cache() -> (ArpCache, TempFile) {
+        let routes = TempFile::new("route", ROUTES);
+        let mut cache = ArpCache::empty(false);
+        cache
+            .learn_gateways(&routes.path, Path::new("/nonexistent/ipv6_route"))
+            .unwrap();
+        (cache, routes)
+    }
+
+    fn gateway_changes(events: &mut EventReceiver) -> Vec<(MacAddr, Severity)> {
+        let mut changes = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let ArpEvent::GatewayChanged { actual, .. } = event {
+                changes.push((actual, event.severity()));
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn gateway_pinned_when_first_seen() {
+        let (mut cache, _routes) = gateway_cache();
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 1], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 1));
+        assert_eq!(
+            gateway_changes(&mut events),
+            [(mac_addr(2), Severity::Critical)]
+        );
+        // The pin did not move
+        assert_eq!(cache.gateways[0].mac, Some(mac_addr(1)));
+        assert_eq!(*cache.entries()[0].mac(), mac_addr(1));
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 2));
+        assert_eq!(gateway_changes(&mut events).len(), 1);
+    }
+
+    #[test]
+    fn gateway_pin_survives_probes() {
+        let (mut cache, _routes) = gateway_cache();
+        let mut events = cache.subscribe();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        cache.update(entry([10, 0, 0, 1], 1, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 1));
+        assert!(cache.start_probe(DEVICE, ip, mac_addr(2)));
+        cache.finish_probe(DEVICE, ip);
+        // Even a migration verdict applied by hand leaves the pin alone
+        cache.migrate(DEVICE, ip, mac_addr(2));
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 5));
+        assert_eq!(gateway_changes(&mut events).len(), 2);
+        assert_eq!(cache.gateways[0].mac, Some(mac_addr(1)));
+    }
+
+    #[test]
+    fn gateway_pins_kept_on_reload() {
+        let (mut cache, routes) = gateway_cache();
+        cache.update(entry([10, 0, 0, 1], 1, BindingSource::Reply, 0));
+        let no_route6 = Path::new("/nonexistent/ipv6_route");
+        cache.learn_gateways(&routes.path, no_route6).unwrap();
+        assert_eq!(cache.gateways[0].mac, Some(mac_addr(1)));
+
+        let mut events = cache.subscribe();
+        cache.update(entry([10, 0, 0, 1], 2, BindingSource::Reply, 1));
+        assert_eq!(gateway_changes(&mut events).len(), 1);
+
+        // No longer routed through
+        let routes = TempFile::new("no-route", ROUTES.lines().next().unwrap());
+        cache.learn_gateways(&routes.path, no_route6).unwrap();
+        assert!(cache.gateways.is_empty());
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let file = TempFile::new("state.json", "");
+        let path = file.path.clone();
+        // Missing database, created on save
+        fs::remove_file(&path).unwrap();
+        let mut cache = ArpCache::empty(false);
+        assert_eq!(cache.load_state(&path).unwrap(), 0);
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 0));
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 5));
+        cache.update(entry([10, 0, 0, 3], 3, BindingSource::Announcement, 7));
+        cache.save_state().unwrap();
+
+        let mut loaded = ArpCache::empty(false);
+        assert_eq!(loaded.load_state(&path).unwrap(), 2);
+        assert_eq!(loaded.entries(), cache.entries());
+        let saved = &loaded.entries()[0];
+        assert_eq!(*saved.first_seen(), at(0));
+        assert_eq!(*saved.last_seen(), at(5));
+        assert_eq!(saved.hits(), 2);
+        assert_eq!(loaded.entries()[1].source(), BindingSource::Announcement);
+
+        // Bindings also in the kernel cache keep their history
+        let mut merged = ArpCache::empty(false);
+        merged.update(entry([10, 0, 0, 2], 2, BindingSource::KernelCache, 10));
+        merged.load_state(&path).unwrap();
+        let binding = &merged.entries()[0];
+        assert_eq!(*binding.first_seen(), at(0));
+        assert_eq!(binding.hits(), 3);
+        assert_eq!(binding.source(), BindingSource::Reply);
+    }
+
+    #[test]
+    fn corrupt_state_moved_aside() {
+        let file = TempFile::new("state.json", "[{\"ip\": ");
+        let mut cache = ArpCache::empty(false);
+        assert!(cache.load_state(&file.path).is_err());
+        assert!(cache.entries().is_empty());
+        assert!(!file.path.exists());
+        let aside = file.path.with_extension("json.corrupt");
+        assert_eq!(fs::read_to_string(aside).unwrap(), "[{\"ip\": ");
+
+        // Saving starts a fresh database
+        cache.update(entry([10, 0, 0, 2], 2, BindingSource::Reply, 0));
+        cache.save_state().unwrap();
+        assert_eq!(ArpCache::empty(false).load_state(&file.path).unwrap(), 1);
+    }
 }
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use log::LevelFilter;
+use pnet::ipnetwork::IpNetwork;
+
+use crate::alert::AlertBackend;
+
+/// Watch for ARP cache changes and alert on new or diverging bindings
+#[derive(Debug, Clone, Parser)]
+#[command(name = "arp-watch", version)]
+pub struct Cli {
+    /// Network interface to watch, repeat for several, defaults to the first
+    /// non loopback one
+    #[arg(short, long)]
+    pub interface: Vec<String>,
+
+    /// List available network interfaces with their addresses and exit
+    #[arg(short, long)]
+    pub list_interfaces: bool,
+
+    /// Record diverging entries instead of only alerting on them
+    #[arg(short, long)]
+    pub follow: bool,
+
+    /// Never send ARP requests, scans are disabled
+    #[arg(long, conflicts_with = "scan")]
+    pub passive: bool,
+
+    /// Switch to this user once the capture is set up
+    #[arg(short, long, value_name = "USER")]
+    pub user: Option<String>,
+
+    /// Log verbosity: off, error, warn, info, debug or trace
+    /// [default: trace for the TUI, info otherwise]
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Where alerts are sent, can be repeated [default: desktop and log, log
+    /// with --daemon]
+    #[arg(short, long = "alert", value_name = "BACKEND", value_enum)]
+    pub alerts: Vec<AlertBackend>,
+
+    /// Trusted bindings file in /etc/ethers format, can be repeated,
+    /// changes on these IPs raise critical alerts
+    #[arg(short, long, value_name = "FILE")]
+    pub trusted: Vec<PathBuf>,
+
+    /// Scan the interface networks once at startup
+    #[arg(short, long)]
+    pub scan: bool,
+
+    /// Scan this CIDR range instead of the interface networks it overlaps,
+    /// can be repeated
+    #[arg(long, value_name = "CIDR", conflicts_with = "passive")]
+    pub scan_target: Vec<IpNetwork>,
+
+    /// Allow scans over scan.max_hosts addresses, e.g. a /8
+    #[arg(long, conflicts_with = "passive")]
+    pub large_scan: bool,
+
+    /// Configuration file [default: /etc/arp-watch/config.toml]
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Output format of reports and listings
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Replay a pcap or pcapng capture through the detector, print a report and exit
+    #[arg(short, long, value_name = "FILE", conflicts_with = "daemon")]
+    pub read: Option<PathBuf>,
+
+    /// Run headless, without the TUI
+    #[arg(short, long)]
+    pub daemon: bool,
+
+    /// Send daemon logs to syslog instead of stderr
+    #[arg(long, requires = "daemon")]
+    pub syslog: bool,
+
+    /// Event socket of the capture daemon [default: /run/arp-watch/events.sock]
+    #[arg(long, value_name = "FILE")]
+    pub socket: Option<PathBuf>,
+
+    /// Run unprivileged, showing and notifying events of a capture daemon
+    /// reached through its socket, headless with --daemon
+    #[arg(long, conflicts_with_all = ["read", "scan", "pidfile"])]
+    pub connect: bool,
+
+    /// Write the daemon pid to this file
+    #[arg(long, value_name = "FILE", requires = "daemon")]
+    pub pidfile: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
@@ -0,0 +1,540 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::{error, info, warn, LevelFilter};
+use pnet::{ipnetwork::IpNetwork, util::MacAddr};
+use serde::Deserialize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+use crate::{
+    alert::{AlertBackend, Severity},
+    arp_cache::{ArpCacheMutex, TrustedBinding, PATH, STATE_PATH},
+    cli::Cli,
+    route::{ROUTE6_PATH, ROUTE_PATH},
+};
+
+pub const CONFIG_PATH: &str = "/etc/arp-watch/config.toml";
+pub const SOCKET_PATH: &str = "/run/arp-watch/events.sock";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Interfaces to watch, the first non loopback one when empty
+    pub interfaces: Vec<String>,
+    pub follow_update: bool,
+    /// Never send ARP requests, scans are refused and CAP_NET_RAW is not kept
+    pub passive: bool,
+    /// Unprivileged account to switch to once the capture is set up
+    pub user: Option<String>,
+    /// Group to switch to, the user primary group when missing
+    pub group: Option<String>,
+    pub log_level: Option<LevelFilter>,
+    pub proc_arp_path: PathBuf,
+    /// Routing table the default gateways are learned from
+    pub proc_route_path: PathBuf,
+    /// IPv6 routing table, default routers are pinned from there
+    pub proc_route6_path: PathBuf,
+    pub state_path: PathBuf,
+    pub trusted: Vec<TrustedBinding>,
+    /// `/etc/ethers` style files of trusted bindings
+    pub trusted_files: Vec<PathBuf>,
+    pub alerts: AlertConfig,
+    pub scan: ScanConfig,
+    pub socket: SocketConfig,
+    pub flapping: FlappingConfig,
+    pub mac_claims: MacClaimsConfig,
+    pub traffic: TrafficConfig,
+    pub ndp: NdpConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Desktop and log when missing, only log in daemon mode where there is
+    /// no desktop session
+    pub backends: Option<Vec<AlertBackend>>,
+    /// Lowest severity sent to each of `backends`, info when missing
+    pub min_severity: HashMap<AlertBackend, Severity>,
+    /// Application name shown on desktop notifications
+    pub app_name: String,
+    /// Alerts allowed per `rate_window` seconds and per sink, 0 for no limit
+    pub rate_limit: u32,
+    pub rate_window: u64,
+    pub webhook: Vec<WebhookConfig>,
+    pub email: Vec<EmailConfig>,
+    pub exec: Vec<ExecConfig>,
+}
+
+/// POST every alert as JSON
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Extra request headers, e.g. an authorization token
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// Mail every alert through an SMTP relay
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub host: String,
+    /// Defaults to the usual port for `tls`
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, for a relay on localhost
+    None,
+    #[default]
+    Starttls,
+    /// Implicit TLS, also known as SMTPS
+    Tls,
+}
+
+/// Run a command for every alert, the event is passed in `ARP_*`
+/// environment variables
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecConfig {
+    pub command: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Seconds before the command is killed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// Event socket of the capture daemon, clients need read and write access
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    pub path: PathBuf,
+    pub mode: u32,
+    /// Group owning the socket, the daemon one when missing
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    pub on_startup: bool,
+    /// Seconds between two scans, 0 to only scan on demand
+    pub interval: u64,
+    /// Deprecated, moved into `targets` when loading
+    pub range: Option<IpNetwork>,
+    /// CIDR ranges to scan instead of every IPv4 network of the interface,
+    /// each on the interfaces whose networks it overlaps
+    pub targets: Vec<IpNetwork>,
+    /// Ranges over this many addresses need confirming, 0 for no limit
+    pub max_hosts: u64,
+}
+
+/// A binding changing MAC more than `changes` times within `window`
+/// seconds is flapping, reported once instead of on every change
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlappingConfig {
+    /// 0 to report every change
+    pub changes: usize,
+    pub window: u64,
+}
+
+/// A MAC claiming more than `max_ips` IPs within `window` seconds is
+/// likely poisoning them, routers and proxy ARP devices go in `allow`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MacClaimsConfig {
+    /// 0 to disable
+    pub max_ips: usize,
+    pub window: u64,
+    pub allow: Vec<MacAddr>,
+}
+
+/// Request sweeps and floods, per interface
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrafficConfig {
+    /// Distinct IPs a MAC may ask for within `scan_window` seconds before
+    /// being reported as scanning, 0 to disable
+    pub scan_targets: usize,
+    pub scan_window: u64,
+    /// ARP frames per second making a storm, 0 to disable
+    pub storm_pps: u32,
+}
+
+/// IPv6 Neighbor Discovery
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NdpConfig {
+    /// MACs allowed to send Router Advertisements, when empty the IPv6
+    /// default router, or else the first router seen, is pinned per interface
+    pub routers: Vec<MacAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interfaces: vec![],
+            follow_update: false,
+            passive: false,
+            user: None,
+            group: None,
+            log_level: None,
+            proc_arp_path: PATH.into(),
+            proc_route_path: ROUTE_PATH.into(),
+            proc_route6_path: ROUTE6_PATH.into(),
+            state_path: STATE_PATH.into(),
+            trusted: vec![],
+            trusted_files: vec![],
+            alerts: AlertConfig::default(),
+            scan: ScanConfig::default(),
+            socket: SocketConfig::default(),
+            flapping: FlappingConfig::default(),
+            mac_claims: MacClaimsConfig::default(),
+            traffic: TrafficConfig::default(),
+            ndp: NdpConfig::default(),
+        }
+    }
+}
+
+impl Default for FlappingConfig {
+    fn default() -> Self {
+        Self {
+            changes: 3,
+            window: 60,
+        }
+    }
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            on_startup: false,
+            interval: 0,
+            range: None,
+            targets: vec![],
+            max_hosts: 65536,
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            path: SOCKET_PATH.into(),
+            mode: 0o660,
+            group: None,
+        }
+    }
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            backends: None,
+            min_severity: HashMap::new(),
+            app_name: "ARP Alert".to_string(),
+            rate_limit: 0,
+            rate_window: 60,
+            webhook: vec![],
+            email: vec![],
+            exec: vec![],
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl WebhookConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl EmailConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl ExecConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl AlertConfig {
+    pub fn rate_window(&self) -> Duration {
+        Duration::from_secs(self.rate_window)
+    }
+}
+
+impl Default for MacClaimsConfig {
+    fn default() -> Self {
+        Self {
+            max_ips: 4,
+            window: 300,
+            allow: vec![],
+        }
+    }
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            scan_targets: 50,
+            scan_window: 10,
+            storm_pps: 500,
+        }
+    }
+}
+
+impl FlappingConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+}
+
+impl MacClaimsConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+}
+
+impl TrafficConfig {
+    pub fn scan_window(&self) -> Duration {
+        Duration::from_secs(self.scan_window)
+    }
+}
+
+impl Config {
+    /// Read the file given on the command line, or the default one when it
+    /// exists, then let command line options take precedence
+    pub fn load(cli: &Cli) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(CONFIG_PATH).exists() => Self::from_file(Path::new(CONFIG_PATH))?,
+            None => Self::default(),
+        };
+        config.apply_cli(cli);
+        for path in config.trusted_files.clone() {
+            let bindings = TrustedBinding::from_ethers(&path)?;
+            info!(
+                "{} trusted bindings from {}",
+                bindings.len(),
+                path.display()
+            );
+            config.trusted.extend(bindings);
+        }
+        Ok(config)
+    }
+
+    /// Settings of an unprivileged client, the system file belongs to the
+    /// capture daemon and only its socket matters here
+    pub fn load_client(cli: &Cli) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => {
+                let mut config = Self::default();
+                if let Ok(system) = Self::from_file(Path::new(CONFIG_PATH)) {
+                    config.socket = system.socket;
+                }
+                config
+            }
+        };
+        config.apply_cli(cli);
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let mut config: Self =
+            toml::from_str(&content).map_err(|e| format!("Invalid {}: {e}", path.display()))?;
+        if let Some(range) = config.scan.range.take() {
+            warn!("scan.range is deprecated, use scan.targets = [\"{range}\"]");
+            config.scan.targets.insert(0, range);
+        }
+        Ok(config)
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if !cli.interface.is_empty() {
+            self.interfaces = cli.interface.clone();
+        }
+        if cli.follow {
+            self.follow_update = true;
+        }
+        if cli.passive {
+            self.passive = true;
+        }
+        if let Some(user) = &cli.user {
+            self.user = Some(user.clone());
+        }
+        if let Some(level) = cli.log_level {
+            self.log_level = Some(level);
+        }
+        if !cli.alerts.is_empty() {
+            self.alerts.backends = Some(cli.alerts.clone());
+        }
+        self.alerts
+            .backends
+            .get_or_insert_with(|| match cli.daemon {
+                true => vec![AlertBackend::Log],
+                false => vec![AlertBackend::Desktop, AlertBackend::Log],
+            });
+        self.trusted_files.extend(cli.trusted.iter().cloned());
+        if cli.scan {
+            self.scan.on_startup = true;
+        }
+        if !cli.scan_target.is_empty() {
+            self.scan.targets = cli.scan_target.clone();
+        }
+        if cli.large_scan {
+            self.scan.max_hosts = 0;
+        }
+        if let Some(path) = &cli.socket {
+            self.socket.path = path.clone();
+        }
+    }
+
+    pub fn config_path(cli: &Cli) -> PathBuf {
+        cli.config.clone().unwrap_or_else(|| CONFIG_PATH.into())
+    }
+}
+
+/// Re-read the configuration on SIGHUP, apply it to the ARP cache and
+/// publish it to the other tasks, capture and cache state are kept
+pub async fn reload_on_sighup(
+    cli: Cli,
+    arp_cache: ArpCacheMutex,
+    config_tx: watch::Sender<Config>,
+) -> std::io::Result<()> {
+    let mut sighup = signal(SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        let path = Config::config_path(&cli);
+        info!("SIGHUP received, reloading {}", path.display());
+        // Trusted files may name hosts, resolving them blocks
+        let load_cli = cli.clone();
+        let config = match tokio::task::spawn_blocking(move || {
+            Config::load(&load_cli).map_err(|e| e.to_string())
+        })
+        .await
+        {
+            Ok(Ok(config)) => config,
+            Ok(Err(e)) => {
+                error!("Keeping the current configuration: {e}");
+                continue;
+            }
+            Err(e) => {
+                error!("Keeping the current configuration, loading failed: {e}");
+                continue;
+            }
+        };
+
+        let previous = config_tx.borrow().clone();
+        if config.interfaces != previous.interfaces
+            || config.state_path != previous.state_path
+            || config.proc_arp_path != previous.proc_arp_path
+            || config.socket != previous.socket
+        {
+            warn!("Interface, socket, state and kernel cache paths changes require a restart");
+        }
+        if config.passive != previous.passive
+            || config.user != previous.user
+            || config.group != previous.group
+        {
+            warn!("Passive mode and privileges changes require a restart");
+        }
+        if let Some(level) = config.log_level {
+            log::set_max_level(level);
+        }
+        {
+            let mut arp_cache = arp_cache.lock().await;
+            arp_cache.configure(&config);
+            // Routes may have changed as well
+            if let Err(e) =
+                arp_cache.learn_gateways(&config.proc_route_path, &config.proc_route6_path)
+            {
+                error!("Failed to read the routing table: {e}");
+            }
+        }
+        config_tx.send_replace(config);
+        info!("Configuration reloaded");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::test_util::TempFile;
+
+    fn backends(config: &str, args: &[&str]) -> Vec<AlertBackend> {
+        let mut config: Config = toml::from_str(config).unwrap();
+        let cli = Cli::parse_from([&["arp-watch"], args].concat());
+        config.apply_cli(&cli);
+        config.alerts.backends.unwrap()
+    }
+
+    #[test]
+    fn default_backends() {
+        use AlertBackend::*;
+        assert_eq!(backends("", &[]), [Desktop, Log]);
+        assert_eq!(backends("", &["--daemon"]), [Log]);
+        assert_eq!(backends("", &["--daemon", "-a", "syslog"]), [Syslog]);
+        let configured = "[alerts]\nbackends = [\"desktop\"]";
+        assert_eq!(backends(configured, &["--daemon"]), [Desktop]);
+    }
+
+    #[test]
+    fn range_moved_to_targets() {
+        let file = TempFile::new(
+            "config.toml",
+            "[scan]\nrange = \"10.0.0.0/24\"\ntargets = [\"10.1.0.0/24\"]",
+        );
+        let config = Config::from_file(&file.path).unwrap();
+        assert_eq!(config.scan.range, None);
+        let targets: Vec<String> = config
+            .scan
+            .targets
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(targets, ["10.0.0.0/24", "10.1.0.0/24"]);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use log::{info, LevelFilter};
+use syslog::Facility;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Where the daemon sends its logs
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum LogTarget {
+    /// Picked up by journald when running as a systemd service
+    Stderr,
+    Syslog,
+}
+
+pub fn init_logger(target: LogTarget, level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    match target {
+        LogTarget::Stderr => env_logger::Builder::new()
+            .filter_level(level)
+            .parse_default_env()
+            .try_init()?,
+        LogTarget::Syslog => syslog::init(Facility::LOG_DAEMON, level, Some("arp-watch"))?,
+    }
+    Ok(())
+}
+
+/// Removes the pidfile when dropped
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Pidfile {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        fs::write(path, format!("{}\n", process::id()))?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::error!("Failed to remove pidfile {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Run headless until SIGTERM or SIGINT is received
+pub async fn wait_for_shutdown() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    info!("Running as a daemon, pid {}", process::id());
+    tokio::select! {
+        _ = sigterm.recv() => info!("SIGTERM received, shutting down"),
+        _ = sigint.recv() => info!("SIGINT received, shutting down"),
+    }
+    Ok(())
+}
@@ -0,0 +1,523 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use chrono::{DateTime, Utc};
+use pnet::{ipnetwork::IpNetwork, util::MacAddr};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{alert::Severity, arp_cache::BindingSource};
+
+/// Events kept for subscribers lagging behind before they start missing some
+pub const EVENT_CAPACITY: usize = 1024;
+
+pub type EventSender = broadcast::Sender<ArpEvent>;
+pub type EventReceiver = broadcast::Receiver<ArpEvent>;
+
+/// Where and when an event was observed
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct EventContext {
+    pub timestamp: DateTime<Utc>,
+    pub interface: String,
+    /// How the binding behind the event was learned
+    pub source: Option<BindingSource>,
+}
+
+/// Something worth reporting, published by the cache and the scanner
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum ArpEvent {
+    NewHost {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        /// Matches a trusted binding
+        trusted: bool,
+    },
+    MacChanged {
+        context: EventContext,
+        ip: IpAddr,
+        old: MacAddr,
+        new: MacAddr,
+        /// A probe will tell whether it is worth an alert
+        #[serde(default)]
+        verifying: bool,
+    },
+    /// A MAC other than the trusted ones claimed a pinned IP
+    TrustedViolation {
+        context: EventContext,
+        ip: IpAddr,
+        expected: Vec<MacAddr>,
+        actual: MacAddr,
+    },
+    /// The default gateway was claimed by a MAC other than the pinned ones
+    GatewayChanged {
+        context: EventContext,
+        ip: IpAddr,
+        expected: Vec<MacAddr>,
+        actual: MacAddr,
+    },
+    /// Unicast requests sent to `expected` and `claimed` to confirm a
+    /// conflict, along with the MACs that answered
+    ProbeCompleted {
+        context: EventContext,
+        ip: IpAddr,
+        expected: Vec<MacAddr>,
+        claimed: MacAddr,
+        answers: Vec<MacAddr>,
+        verdict: ProbeVerdict,
+    },
+    /// Two MACs or more claiming the same IP at once
+    DuplicateIp {
+        context: EventContext,
+        ip: IpAddr,
+        macs: Vec<MacAddr>,
+    },
+    /// The binding changed MAC `changes` times within `window` seconds,
+    /// further changes are not reported until it settles
+    Flapping {
+        context: EventContext,
+        ip: IpAddr,
+        macs: Vec<MacAddr>,
+        changes: usize,
+        window: u64,
+    },
+    /// One MAC answering for many IPs, a man in the middle or proxy ARP
+    MacClaimsIps {
+        context: EventContext,
+        mac: MacAddr,
+        ips: Vec<IpAddr>,
+    },
+    /// Reply to `target` that no request asked for, the signature of ARP
+    /// spoofing tools
+    UnsolicitedReply {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        target: IpAddr,
+    },
+    /// Bogus or inconsistent frame, common in crafted packets
+    Anomaly {
+        context: EventContext,
+        /// ARP sender or IPv6 source, unless the packet is malformed
+        ip: Option<IpAddr>,
+        anomaly: Anomaly,
+        /// Anomalies of that kind seen on the interface so far
+        count: u64,
+        /// Raw frame, hex encoded
+        frame: String,
+    },
+    /// `mac` asked for `targets` distinct IPs within `window` seconds
+    ArpScan {
+        context: EventContext,
+        mac: MacAddr,
+        targets: usize,
+        window: u64,
+    },
+    /// `pps` ARP frames during a second on the interface, over the limit
+    ArpStorm { context: EventContext, pps: u32 },
+    /// RFC 5227 probe, a host checking whether `ip` is free before using it
+    AddressProbe {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        /// MACs currently bound to `ip`
+        holders: Vec<MacAddr>,
+    },
+    /// `mac` answered a probe from `prober`, keeping `ip` for itself
+    AddressDefended {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        prober: MacAddr,
+    },
+    /// Router Advertisement from a MAC other than the allowed or pinned
+    /// routers, hosts may take it as their default route
+    RogueRouter {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        expected: Vec<MacAddr>,
+        /// Seconds, 0 when the router withdraws itself
+        lifetime: u16,
+        prefixes: Vec<IpNetwork>,
+    },
+    /// Router trusted to advertise on the interface from now on, the next
+    /// hop of the IPv6 default route or else the first router heard
+    RouterPinned {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+        /// Pinned from the kernel default route
+        from_route: bool,
+    },
+    /// Unsolicited announcement of a binding
+    GratuitousArp {
+        context: EventContext,
+        ip: IpAddr,
+        mac: MacAddr,
+    },
+    ScanCompleted {
+        context: EventContext,
+        range: IpNetwork,
+        requests: usize,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// Ethernet source and ARP sender hardware address differ
+    SenderMismatch {
+        ethernet: MacAddr,
+        arp: MacAddr,
+    },
+    /// Broadcast or multicast ARP sender hardware address
+    MulticastSender {
+        mac: MacAddr,
+    },
+    /// Reply sent to the Ethernet broadcast address
+    BroadcastReply {
+        mac: MacAddr,
+    },
+    /// Too short to hold an ARP or Neighbor Discovery packet, or with
+    /// bogus options
+    Malformed {
+        length: usize,
+    },
+    HardwareType {
+        hw_type: u16,
+    },
+    ProtocolType {
+        protocol: u16,
+    },
+    /// Hardware and protocol address lengths other than 6 and 4
+    AddressLength {
+        hw_len: u8,
+        proto_len: u8,
+    },
+    /// Neither request nor reply, e.g. RARP or InARP
+    Opcode {
+        opcode: u16,
+    },
+    /// Sender outside the interface subnets
+    ForeignSender {
+        ip: Ipv4Addr,
+    },
+    /// Unspecified, loopback, multicast, broadcast or reserved address
+    Martian {
+        ip: IpAddr,
+    },
+    /// Neighbor Discovery message that went through a router, only hop
+    /// limit 255 proves it was sent on link
+    HopLimit {
+        hop_limit: u8,
+    },
+}
+
+/// What the MACs answering a probe tell about a conflict
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeVerdict {
+    /// Only the new MAC answers, e.g. a NIC swap or a VM moved
+    Migration,
+    /// Several MACs answer for the same IP
+    Spoofing,
+    /// The new MAC is gone already
+    Transient,
+}
+
+impl ProbeVerdict {
+    pub fn classify(claimed: MacAddr, answers: &[MacAddr]) -> Self {
+        match answers {
+            [mac] if *mac == claimed => Self::Migration,
+            [_, _, ..] => Self::Spoofing,
+            _ => Self::Transient,
+        }
+    }
+}
+
+impl EventContext {
+    pub fn new(timestamp: DateTime<Utc>, interface: &str, source: Option<BindingSource>) -> Self {
+        Self {
+            timestamp,
+            interface: interface.to_string(),
+            source,
+        }
+    }
+}
+
+impl ArpEvent {
+    pub fn context(&self) -> &EventContext {
+        match self {
+            Self::NewHost { context, .. }
+            | Self::MacChanged { context, .. }
+            | Self::TrustedViolation { context, .. }
+            | Self::GatewayChanged { context, .. }
+            | Self::ProbeCompleted { context, .. }
+            | Self::DuplicateIp { context, .. }
+            | Self::Flapping { context, .. }
+            | Self::MacClaimsIps { context, .. }
+            | Self::UnsolicitedReply { context, .. }
+            | Self::Anomaly { context, .. }
+            | Self::ArpScan { context, .. }
+            | Self::ArpStorm { context, .. }
+            | Self::AddressProbe { context, .. }
+            | Self::AddressDefended { context, .. }
+            | Self::RogueRouter { context, .. }
+            | Self::RouterPinned { context, .. }
+            | Self::GratuitousArp { context, .. }
+            | Self::ScanCompleted { context, .. } => context,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::TrustedViolation { .. }
+            | Self::GatewayChanged { .. }
+            | Self::DuplicateIp { .. }
+            | Self::Flapping { .. }
+            | Self::MacClaimsIps { .. }
+            | Self::RogueRouter { .. } => Severity::Critical,
+            Self::ProbeCompleted {
+                verdict: ProbeVerdict::Spoofing,
+                ..
+            } => Severity::Critical,
+            _ => Severity::Info,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NewHost { .. } => "NewHost",
+            Self::MacChanged { .. } => "MacChanged",
+            Self::TrustedViolation { .. } => "TrustedViolation",
+            Self::GatewayChanged { .. } => "GatewayChanged",
+            Self::ProbeCompleted { .. } => "ProbeCompleted",
+            Self::DuplicateIp { .. } => "DuplicateIp",
+            Self::Flapping { .. } => "Flapping",
+            Self::MacClaimsIps { .. } => "MacClaimsIps",
+            Self::UnsolicitedReply { .. } => "UnsolicitedReply",
+            Self::Anomaly { .. } => "Anomaly",
+            Self::ArpScan { .. } => "ArpScan",
+            Self::ArpStorm { .. } => "ArpStorm",
+            Self::AddressProbe { .. } => "AddressProbe",
+            Self::AddressDefended { .. } => "AddressDefended",
+            Self::RogueRouter { .. } => "RogueRouter",
+            Self::RouterPinned { .. } => "RouterPinned",
+            Self::GratuitousArp { .. } => "GratuitousArp",
+            Self::ScanCompleted { .. } => "ScanCompleted",
+        }
+    }
+}
+
+impl fmt::Display for ArpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NewHost { ip, mac, .. } => write!(f, "{} at {}", ip, mac),
+            Self::MacChanged { ip, old, new, .. } => {
+                write!(f, "[{}]\nwas {}, now {}", ip, old, new)
+            }
+            Self::TrustedViolation {
+                ip,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "[{}]\npinned to {}, now {}",
+                ip,
+                join_macs(expected),
+                actual
+            ),
+            Self::GatewayChanged {
+                ip,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "[{}] gateway\nwas {}, now {}",
+                ip,
+                join_macs(expected),
+                actual
+            ),
+            Self::ProbeCompleted {
+                ip,
+                claimed,
+                answers,
+                verdict,
+                ..
+            } => match verdict {
+                ProbeVerdict::Migration => write!(f, "[{}]\nmoved to {}", ip, claimed),
+                ProbeVerdict::Spoofing => {
+                    write!(f, "[{}]\nspoofed, answered by {}", ip, join_macs(answers))
+                }
+                ProbeVerdict::Transient => write!(f, "[{}]\n{} did not answer", ip, claimed),
+            },
+            Self::DuplicateIp { ip, macs, .. } => {
+                write!(f, "[{}]\nclaimed by {}", ip, join_macs(macs))
+            }
+            Self::Flapping {
+                ip,
+                macs,
+                changes,
+                window,
+                ..
+            } => write!(
+                f,
+                "[{}]\nflapping between {}, {} changes in {}s",
+                ip,
+                join_macs(macs),
+                changes,
+                window
+            ),
+            Self::MacClaimsIps { mac, ips, .. } => {
+                let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+                write!(f, "{} claims {} IPs\n{}", mac, ips.len(), ips.join(", "))
+            }
+            Self::UnsolicitedReply {
+                ip, mac, target, ..
+            } => write!(f, "[{}]\nunsolicited reply from {} to {}", ip, mac, target),
+            Self::Anomaly {
+                ip, anomaly, count, ..
+            } => match ip {
+                Some(ip) => write!(f, "[{}]\n{} ({} seen)", ip, anomaly, count),
+                None => write!(f, "{} ({} seen)", anomaly, count),
+            },
+            Self::ArpScan {
+                mac,
+                targets,
+                window,
+                ..
+            } => write!(
+                f,
+                "ARP scan detected\n{} asked for {} IPs in {}s",
+                mac, targets, window
+            ),
+            Self::ArpStorm { context, pps } => write!(
+                f,
+                "ARP storm on {}\n{} frames per second",
+                context.interface, pps
+            ),
+            Self::AddressProbe {
+                ip, mac, holders, ..
+            } => match holders.is_empty() {
+                true => write!(f, "{} probed by {}", ip, mac),
+                false => write!(
+                    f,
+                    "[{}]\nprobed by {}, held by {}",
+                    ip,
+                    mac,
+                    join_macs(holders)
+                ),
+            },
+            Self::AddressDefended {
+                ip, mac, prober, ..
+            } => write!(f, "[{}]\ndefended by {} against {}", ip, mac, prober),
+            Self::RogueRouter {
+                ip,
+                mac,
+                expected,
+                prefixes,
+                ..
+            } => {
+                let prefixes: Vec<String> =
+                    prefixes.iter().map(|prefix| prefix.to_string()).collect();
+                write!(
+                    f,
+                    "[{}]\nrogue router {}, expected {}\nadvertising {}",
+                    ip,
+                    mac,
+                    join_macs(expected),
+                    match prefixes.is_empty() {
+                        true => "no prefix".to_string(),
+                        false => prefixes.join(", "),
+                    }
+                )
+            }
+            Self::RouterPinned {
+                ip,
+                mac,
+                from_route,
+                ..
+            } => write!(
+                f,
+                "[{}]\nrouter pinned to {}{}",
+                ip,
+                mac,
+                match from_route {
+                    true => ", the default route next hop",
+                    false => ", the first one heard",
+                }
+            ),
+            Self::GratuitousArp { ip, mac, .. } => write!(f, "{} announced at {}", ip, mac),
+            Self::ScanCompleted {
+                context,
+                range,
+                requests,
+            } => write!(
+                f,
+                "Scan of {} on {} done, {} requests sent",
+                range, context.interface, requests
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SenderMismatch { ethernet, arp } => {
+                write!(f, "sent by {} on behalf of {}", ethernet, arp)
+            }
+            Self::MulticastSender { mac } => write!(f, "multicast sender {}", mac),
+            Self::BroadcastReply { mac } => write!(f, "reply from {} sent to broadcast", mac),
+            Self::Malformed { length } => write!(f, "malformed packet of {} bytes", length),
+            Self::HardwareType { hw_type } => write!(f, "hardware type {:#06x}", hw_type),
+            Self::ProtocolType { protocol } => write!(f, "protocol type {:#06x}", protocol),
+            Self::AddressLength { hw_len, proto_len } => {
+                write!(f, "address lengths {} and {}", hw_len, proto_len)
+            }
+            Self::Opcode { opcode } => {
+                let name = match opcode {
+                    3 | 4 => "RARP",
+                    8 | 9 => "InARP",
+                    _ => "unknown",
+                };
+                write!(f, "{} opcode {}", name, opcode)
+            }
+            Self::ForeignSender { ip } => write!(f, "sender {} outside the interface subnets", ip),
+            Self::Martian { ip } => write!(f, "martian address {}", ip),
+            Self::HopLimit { hop_limit } => {
+                write!(f, "Neighbor Discovery with hop limit {}", hop_limit)
+            }
+        }
+    }
+}
+
+impl Anomaly {
+    /// Counters are kept by kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::SenderMismatch { .. } => "sender_mismatch",
+            Self::MulticastSender { .. } => "multicast_sender",
+            Self::BroadcastReply { .. } => "broadcast_reply",
+            Self::Malformed { .. } => "malformed",
+            Self::HardwareType { .. } => "hardware_type",
+            Self::ProtocolType { .. } => "protocol_type",
+            Self::AddressLength { .. } => "address_length",
+            Self::Opcode { .. } => "opcode",
+            Self::ForeignSender { .. } => "foreign_sender",
+            Self::Martian { .. } => "martian",
+            Self::HopLimit { .. } => "hop_limit",
+        }
+    }
+}
+
+fn join_macs(macs: &[MacAddr]) -> String {
+    let macs: Vec<String> = macs.iter().map(|mac| mac.to_string()).collect();
+    macs.join(", ")
+}
@@ -0,0 +1,541 @@
+use std::{
+    collections::HashMap,
+    fs::{self, Permissions},
+    io,
+    net::IpAddr,
+    os::unix::{fs::PermissionsExt, net},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+use nix::{
+    sys::stat::{umask, Mode},
+    unistd::{chown, Group},
+};
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    arp_cache::{ArpCacheMutex, ArpEntry},
+    config::SocketConfig,
+    event::{ArpEvent, EventReceiver, EventSender},
+    net_arp::{net_arp_sender::scan_network, NetArpSenderMutex},
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Binding changes are pushed to every client at most this often, hit counts
+/// included
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Binding changes a client may fall behind on before getting a snapshot
+const CHANGES_CAPACITY: usize = 16;
+
+/// Client requests are a few dozen bytes, longer lines are refused
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Sent by the capture daemon, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Current state of the cache, on connection
+    Bindings {
+        entries: Vec<ArpEntry>,
+        follow_update: bool,
+    },
+    /// Bindings added, changed or removed since the previous message, pushed
+    /// to every client at once
+    BindingsChanged {
+        updated: Vec<ArpEntry>,
+        removed: Vec<BindingKey>,
+        follow_update: bool,
+    },
+    Event {
+        event: ArpEvent,
+    },
+}
+
+/// Interface, IP and MAC identifying a binding
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BindingKey {
+    pub device: String,
+    pub ip: IpAddr,
+    pub mac: MacAddr,
+}
+
+impl From<&ArpEntry> for BindingKey {
+    fn from(entry: &ArpEntry) -> Self {
+        Self {
+            device: entry.device().to_string(),
+            ip: *entry.ip(),
+            mac: *entry.mac(),
+        }
+    }
+}
+
+/// Requests a client may send, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Scan one interface, or all of them. Ranges over `scan.max_hosts`
+    /// addresses are refused, only the daemon command line allows them
+    Scan {
+        #[serde(default)]
+        interface: Option<String>,
+    },
+    SetFollow {
+        follow_update: bool,
+    },
+}
+
+/// Unix socket streaming events to unprivileged clients, removed when dropped
+pub struct EventServer {
+    /// Bound before the runtime starts, while still privileged
+    listener: net::UnixListener,
+    path: PathBuf,
+}
+
+impl EventServer {
+    pub fn bind(config: &SocketConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Left over by a previous run that did not shut down cleanly
+        if config.path.exists() {
+            fs::remove_file(&config.path)?;
+        }
+        // Nobody may connect before the group and mode are set
+        let umask_before = umask(Mode::from_bits_truncate(0o177));
+        let listener = net::UnixListener::bind(&config.path);
+        umask(umask_before);
+        let listener = listener?;
+        listener.set_nonblocking(true)?;
+        let server = Self {
+            listener,
+            path: config.path.clone(),
+        };
+
+        if let Some(group) = &config.group {
+            let gid = match Group::from_name(group)? {
+                Some(group) => group.gid,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no group named {group}"),
+                    ))
+                }
+            };
+            chown(&config.path, None, Some(gid))?;
+        }
+        fs::set_permissions(&config.path, Permissions::from_mode(config.mode))?;
+        info!("Serving events on {}", config.path.display());
+        Ok(server)
+    }
+
+    pub async fn serve(
+        self,
+        arp_cache: ArpCacheMutex,
+        events: EventSender,
+        senders: Vec<NetArpSenderMutex>,
+    ) -> io::Result<()> {
+        let listener = UnixListener::from_std(self.listener.try_clone()?)?;
+        let published = {
+            let arp_cache = arp_cache.lock().await;
+            Arc::new(Mutex::new(Published {
+                entries: arp_cache.entries(),
+                follow_update: arp_cache.follow_update,
+            }))
+        };
+        let (changes, _) = broadcast::channel(CHANGES_CAPACITY);
+        tokio::spawn(publish(
+            arp_cache.clone(),
+            published.clone(),
+            changes.clone(),
+        ));
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            debug!("Event client connected");
+            let client = ServerConnection {
+                arp_cache: arp_cache.clone(),
+                senders: senders.clone(),
+                published: published.clone(),
+            };
+            // Changes are sent under the lock, none is missed or applied twice
+            let (bindings, changes) = {
+                let published = published.lock().unwrap();
+                (published.bindings(), changes.subscribe())
+            };
+            let events = events.subscribe();
+            tokio::spawn(async move {
+                if let Err(e) = client.run(stream, bindings, events, changes).await {
+                    debug!("Event client disconnected: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// Bindings as last pushed to the clients
+struct Published {
+    entries: Vec<ArpEntry>,
+    follow_update: bool,
+}
+
+impl Published {
+    fn bindings(&self) -> ServerMessage {
+        ServerMessage::Bindings {
+            entries: self.entries.clone(),
+            follow_update: self.follow_update,
+        }
+    }
+}
+
+/// Push the binding changes to every client, computed once for all of them
+async fn publish(
+    arp_cache: ArpCacheMutex,
+    published: Arc<Mutex<Published>>,
+    changes: broadcast::Sender<ServerMessage>,
+) {
+    let mut interval = time::interval(PUBLISH_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let (entries, follow_update) = {
+            let arp_cache = arp_cache.lock().await;
+            (arp_cache.entries(), arp_cache.follow_update)
+        };
+        let mut published = published.lock().unwrap();
+        let (updated, removed) = delta(&index(&published.entries), &entries);
+        if updated.is_empty() && removed.is_empty() && follow_update == published.follow_update {
+            continue;
+        }
+        published.entries = entries;
+        published.follow_update = follow_update;
+        // Nobody connected is not an error
+        let _ = changes.send(ServerMessage::BindingsChanged {
+            updated,
+            removed,
+            follow_update,
+        });
+    }
+}
+
+impl Drop for EventServer {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("Failed to remove socket {}: {e}", self.path.display());
+        }
+    }
+}
+
+struct ServerConnection {
+    arp_cache: ArpCacheMutex,
+    senders: Vec<NetArpSenderMutex>,
+    published: Arc<Mutex<Published>>,
+}
+
+impl ServerConnection {
+    async fn run(
+        &self,
+        stream: UnixStream,
+        bindings: ServerMessage,
+        mut events: EventReceiver,
+        mut changes: broadcast::Receiver<ServerMessage>,
+    ) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+        write_message(&mut writer, &bindings).await?;
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("{} events missed by a client", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return Ok(()),
+                    };
+                    write_message(&mut writer, &ServerMessage::Event { event }).await?;
+                }
+                changed = changes.recv() => {
+                    let changed = match changed {
+                        Ok(changed) => changed,
+                        // Deltas cannot be skipped, start over from a snapshot
+                        Err(RecvError::Lagged(_)) => {
+                            changes = changes.resubscribe();
+                            self.published.lock().unwrap().bindings()
+                        }
+                        Err(RecvError::Closed) => return Ok(()),
+                    };
+                    write_message(&mut writer, &changed).await?;
+                }
+                read = read_line(&mut reader, &mut line, MAX_REQUEST_LEN) => {
+                    if !read? {
+                        return Ok(());
+                    }
+                    match serde_json::from_slice(&line) {
+                        Ok(message) => self.handle(message).await?,
+                        Err(e) => warn!("Invalid client request: {e}"),
+                    }
+                    line.clear();
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, message: ClientMessage) -> io::Result<()> {
+        match message {
+            ClientMessage::Scan { interface } => {
+                info!("Scan requested by a client");
+                if self.senders.is_empty() {
+                    warn!("Passive mode, scan refused");
+                }
+                for sender in self.senders.iter() {
+                    let sender = sender.clone();
+                    let interface = interface.clone();
+                    tokio::spawn(async move {
+                        let device = sender.lock().await.device().to_string();
+                        if interface.is_some_and(|interface| interface != device) {
+                            return;
+                        }
+                        if let Err(e) = scan_network(&sender, false).await {
+                            error!("Scan hosts failed {e}");
+                        }
+                    });
+                }
+            }
+            ClientMessage::SetFollow { follow_update } => {
+                info!("Follow mode set to {} by a client", follow_update);
+                self.arp_cache.lock().await.follow_update = follow_update;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn index(entries: &[ArpEntry]) -> HashMap<BindingKey, ArpEntry> {
+    entries
+        .iter()
+        .map(|entry| (entry.into(), entry.clone()))
+        .collect()
+}
+
+/// Bindings of `entries` not in `sent` or changed since, and keys of those
+/// no longer in `entries`
+fn delta(
+    sent: &HashMap<BindingKey, ArpEntry>,
+    entries: &[ArpEntry],
+) -> (Vec<ArpEntry>, Vec<BindingKey>) {
+    let current = index(entries);
+    let updated = entries
+        .iter()
+        .filter(|entry| sent.get(&BindingKey::from(*entry)) != Some(*entry))
+        .cloned()
+        .collect();
+    let removed = sent
+        .keys()
+        .filter(|key| !current.contains_key(*key))
+        .cloned()
+        .collect();
+    (updated, removed)
+}
+
+/// Apply a `BindingsChanged` to `entries`, keeping their order
+fn apply_delta(entries: &mut Vec<ArpEntry>, updated: Vec<ArpEntry>, removed: &[BindingKey]) {
+    entries.retain(|entry| !removed.contains(&entry.into()));
+    for entry in updated {
+        let key = BindingKey::from(&entry);
+        match entries
+            .iter_mut()
+            .find(|known| BindingKey::from(&**known) == key)
+        {
+            Some(known) => *known = entry,
+            None => entries.push(entry),
+        }
+    }
+}
+
+/// Read a line into `line` without its newline, `Ok(false)` at the end of
+/// the stream. Cancel safe, a partial line is kept in `line`
+async fn read_line<R>(reader: &mut R, line: &mut Vec<u8>, max_len: usize) -> io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let limit = max_len.saturating_sub(line.len()) as u64 + 1;
+    let read = reader.take(limit).read_until(b'\n', line).await?;
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        return Ok(true);
+    }
+    if line.len() > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line over {max_len} bytes"),
+        ));
+    }
+    match read {
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+async fn write_message<W, M>(writer: &mut W, message: &M) -> io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+    M: Serialize,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+/// Mirror the daemon cache into `arp_cache` and republish its events on
+/// `events`, reconnecting whenever the daemon goes away
+pub async fn event_client(
+    path: PathBuf,
+    arp_cache: ArpCacheMutex,
+    events: EventSender,
+    mut commands: mpsc::Receiver<ClientMessage>,
+) {
+    loop {
+        match UnixStream::connect(&path).await {
+            Ok(stream) => {
+                info!("Connected to {}", path.display());
+                if let Err(e) = client_session(stream, &arp_cache, &events, &mut commands).await {
+                    error!("Connection to the capture daemon lost: {e}");
+                }
+            }
+            Err(e) => error!("Failed to connect to {}: {e}", path.display()),
+        }
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn client_session(
+    stream: UnixStream,
+    arp_cache: &ArpCacheMutex,
+    events: &EventSender,
+    commands: &mut mpsc::Receiver<ClientMessage>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    None => return Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+                match serde_json::from_str(&line) {
+                    Ok(ServerMessage::Bindings { entries, follow_update }) => {
+                        let mut arp_cache = arp_cache.lock().await;
+                        arp_cache.replace_entries(entries);
+                        arp_cache.follow_update = follow_update;
+                    }
+                    Ok(ServerMessage::BindingsChanged { updated, removed, follow_update }) => {
+                        let mut arp_cache = arp_cache.lock().await;
+                        let mut entries = arp_cache.entries();
+                        apply_delta(&mut entries, updated, &removed);
+                        arp_cache.replace_entries(entries);
+                        arp_cache.follow_update = follow_update;
+                    }
+                    Ok(ServerMessage::Event { event }) => {
+                        // Nobody listening is not an error
+                        let _ = events.send(event);
+                    }
+                    Err(e) => warn!("Invalid message from the capture daemon: {e}"),
+                }
+            }
+            Some(command) = commands.recv() => {
+                write_message(&mut writer, &command).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        arp_cache::{ArpCache, BindingSource},
+        test_util::*,
+    };
+
+    use super::*;
+
+    fn entry(last: u8, mac: u8) -> ArpEntry {
+        let ip = IpAddr::from([10, 0, 0, last]);
+        ArpEntry::new(ip, mac_addr(mac), BindingSource::Reply, at(0)).with_device("eth0")
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let before = vec![entry(1, 1), entry(2, 2), entry(3, 3)];
+        let after = vec![entry(1, 1), entry(3, 4), entry(4, 4)];
+        let (updated, removed) = delta(&index(&before), &after);
+        assert_eq!(updated, [entry(3, 4), entry(4, 4)]);
+        assert_eq!(removed.len(), 2);
+
+        let mut mirrored = before.clone();
+        apply_delta(&mut mirrored, updated, &removed);
+        assert_eq!(mirrored, after);
+        assert_eq!(delta(&index(&after), &after), (vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn changes_published_once() {
+        let arp_cache = Arc::new(tokio::sync::Mutex::new(ArpCache::empty(false)));
+        let published = Arc::new(Mutex::new(Published {
+            entries: vec![],
+            follow_update: false,
+        }));
+        let (changes, mut first) = broadcast::channel(CHANGES_CAPACITY);
+        let mut second = changes.subscribe();
+        tokio::spawn(publish(arp_cache.clone(), published.clone(), changes));
+
+        arp_cache.lock().await.update(entry(1, 1));
+        for client in [&mut first, &mut second] {
+            let changed = time::timeout(2 * PUBLISH_INTERVAL, client.recv()).await;
+            let Ok(Ok(ServerMessage::BindingsChanged { updated, .. })) = changed else {
+                panic!("no bindings changed");
+            };
+            assert_eq!(updated, [entry(1, 1)]);
+        }
+        assert_eq!(published.lock().unwrap().entries, [entry(1, 1)]);
+
+        let unchanged = time::timeout(2 * PUBLISH_INTERVAL, first.recv()).await;
+        assert!(unchanged.is_err());
+    }
+
+    #[tokio::test]
+    async fn bounded_lines() {
+        let input = b"short\npartial";
+        let mut reader = BufReader::new(&input[..]);
+        let mut line = vec![];
+        assert!(read_line(&mut reader, &mut line, 8).await.unwrap());
+        assert_eq!(line, b"short");
+        line.clear();
+        assert!(read_line(&mut reader, &mut line, 8).await.unwrap());
+        assert_eq!(line, b"partial");
+        line.clear();
+        assert!(!read_line(&mut reader, &mut line, 8).await.unwrap());
+
+        let mut reader = BufReader::new(&b"much too long\n"[..]);
+        let error = read_line(&mut reader, &mut line, 8).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,4 +1,15 @@
 pub mod alert;
 pub mod arp_cache;
+pub mod cli;
+pub mod config;
+pub mod daemon;
+pub mod event;
+pub mod event_socket;
+pub mod neighbour_monitor;
 pub mod net_arp;
+pub mod privileges;
+pub mod replay;
+pub mod route;
+#[cfg(test)]
+mod test_util;
 pub mod ui;
@@ -1,24 +1,333 @@
-use std::{env, sync::Arc};
+use std::{path::Path, process, sync::Arc, thread, time::Duration};
 
-use arp_watch::{arp_cache::ArpCache, net_arp::*, ui::*};
-use tokio::sync::Mutex;
+use arp_watch::{
+    alert::{self, Alerter},
+    arp_cache::ArpCache,
+    cli::{Cli, OutputFormat},
+    config::{self, Config},
+    daemon::{self, LogTarget, Pidfile},
+    event::EVENT_CAPACITY,
+    event_socket::{self, EventServer},
+    neighbour_monitor::NeighbourMonitor,
+    net_arp::*,
+    privileges, replay,
+    ui::*,
+};
+use clap::Parser;
+use log::{error, LevelFilter};
+use net_arp_sender::{scan_network, scan_schedule};
+use serde_json::json;
+use tokio::{
+    runtime::{Handle, Runtime},
+    sync::{broadcast, mpsc, watch, Mutex},
+    time,
+};
 
-#[tokio::main]
-async fn main() {
-    tui_logger::init_logger(log::LevelFilter::Trace).unwrap();
-    let arp_cache = ArpCache::new(false);
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn main() {
+    let cli = Cli::parse();
+
+    if cli.list_interfaces {
+        list_interfaces(cli.format);
+        return;
+    }
+
+    if let Some(path) = &cli.read {
+        if let Err(e) = runtime().block_on(replay::replay(path, cli.format)) {
+            eprintln!("Failed to replay {}: {e}", path.display());
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Loggers let everything through, the level can then change on reload.
+    // Installed first so that warnings while loading the config are kept
+    let default_level = match cli.daemon {
+        true => LevelFilter::Info,
+        false => LevelFilter::Trace,
+    };
+    if cli.daemon {
+        let log_target = match cli.syslog {
+            true => LogTarget::Syslog,
+            false => LogTarget::Stderr,
+        };
+        if let Err(e) = daemon::init_logger(log_target, LevelFilter::Trace) {
+            eprintln!("Failed to initialise logging: {e}");
+            process::exit(1);
+        }
+    } else {
+        tui_logger::init_logger(LevelFilter::Trace).unwrap();
+    }
+    log::set_max_level(default_level);
+
+    let config = match cli.connect {
+        true => Config::load_client(&cli),
+        false => Config::load(&cli),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+    log::set_max_level(config.log_level.unwrap_or(default_level));
+
+    if cli.connect {
+        runtime().block_on(connect(&cli, config));
+        return;
+    }
+
+    let net_watchers = match NetArpWatcher::all(&config.interfaces) {
+        Ok(net_watchers) => net_watchers,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+
+    let (events, alert_events) = broadcast::channel(EVENT_CAPACITY);
+    let alerter = Alerter::new(&config.alerts);
+
+    let devices = net_watchers
+        .iter()
+        .map(|net_watcher| net_watcher.interface().name.clone())
+        .collect();
+    let mut arp_cache = ArpCache::from_config(&config, devices, events.clone());
+    if let Err(e) = arp_cache.load_state(&config.state_path) {
+        error!("Failed to load binding database: {e}");
+    }
     let arp_cache_mutex = Arc::new(Mutex::new(arp_cache));
 
-    let net_watcher = NetArpWatcher::new(env::args().nth(1));
-    let mut listener = net_watcher.listener(arp_cache_mutex.clone());
-    let sender = net_watcher.sender();
+    // Everything needing privileges is opened before dropping them
+    let listeners: Vec<NetArpListener> = net_watchers
+        .iter()
+        .map(|net_watcher| net_watcher.listener(arp_cache_mutex.clone()))
+        .collect();
+    let sender_mutexes: Vec<NetArpSenderMutex> = match config.passive {
+        true => vec![],
+        false => net_watchers
+            .iter()
+            .map(|net_watcher| {
+                let mut sender = net_watcher.sender();
+                sender.configure(&config.scan);
+                sender.events = Some(events.clone());
+                Arc::new(Mutex::new(sender))
+            })
+            .collect(),
+    };
+    if let Some(user) = &config.user {
+        let writable_dirs: Vec<&Path> = [
+            Some(config.state_path.as_path()),
+            Some(config.socket.path.as_path()),
+            cli.pidfile.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.parent())
+        .collect();
+        if let Err(e) = privileges::hand_over_dirs(user, config.group.as_deref(), &writable_dirs) {
+            error!("Failed to prepare directories: {e}");
+            process::exit(1);
+        }
+    }
+
+    let mut server = None;
+    if cli.daemon {
+        server = match EventServer::bind(&config.socket) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                error!(
+                    "Failed to serve events on {}: {e}",
+                    config.socket.path.display()
+                );
+                None
+            }
+        };
+    }
+
+    if let Some(user) = &config.user {
+        if let Err(e) =
+            privileges::drop_privileges(user, config.group.as_deref(), !sender_mutexes.is_empty())
+        {
+            error!("Failed to drop privileges: {e}");
+            process::exit(1);
+        }
+    }
+
+    // Written by the user it runs as, so that it can be removed on exit
+    let _pidfile = match cli.pidfile.as_deref().map(Pidfile::create).transpose() {
+        Ok(pidfile) => pidfile,
+        Err(e) => {
+            error!("Failed to write pidfile: {e}");
+            process::exit(1);
+        }
+    };
+
+    let runtime = runtime();
+    runtime.block_on(async {
+        let mut tasks = vec![];
+        // Reading frames blocks, keep each interface off the runtime workers
+        let handle = Handle::current();
+        for mut listener in listeners {
+            let handle = handle.clone();
+            thread::Builder::new()
+                .name(format!("listen {}", listener.device()))
+                .spawn(move || {
+                    if let Err(e) = handle.block_on(listener.packet_handler()) {
+                        error!("Packet handler on {} stopped: {e}", listener.device());
+                    }
+                })
+                .expect("Failed to start a listener thread");
+        }
 
-    let app = App::new(arp_cache_mutex, sender);
+        for net_watcher in net_watchers.iter() {
+            match NeighbourMonitor::new(net_watcher.interface(), arp_cache_mutex.clone()) {
+                Ok(mut monitor) => tasks.push(tokio::spawn(async move {
+                    if let Err(e) = monitor.event_handler().await {
+                        error!("Neighbour table monitor stopped: {e}");
+                    }
+                })),
+                Err(e) => error!("Failed to subscribe to neighbour table changes: {e}"),
+            }
+        }
 
-    let listener_th = tokio::spawn(async move {
-        listener.packet_handler().await.unwrap();
+        let state_cache = arp_cache_mutex.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = time::interval(STATE_SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state_cache.lock().await.save_state() {
+                    error!("Failed to save binding database: {e}");
+                }
+            }
+        }));
+
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let reload_cache = arp_cache_mutex.clone();
+        let reload_cli = cli.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = config::reload_on_sighup(reload_cli, reload_cache, config_tx).await {
+                error!("Failed to install SIGHUP handler: {e}");
+            }
+        }));
+
+        // Sinks block on network and child processes, keep them off the runtime
+        let alert_config_rx = config_rx.clone();
+        thread::Builder::new()
+            .name("alerts".to_string())
+            .spawn(move || alert::alert_handler(alerter, alert_events, alert_config_rx))
+            .expect("Failed to start the alert thread");
+
+        // A reload may enable scheduled scans later on
+        for scan_sender in sender_mutexes.iter().cloned() {
+            let on_startup = config.scan.on_startup;
+            let config_rx = config_rx.clone();
+            tasks.push(tokio::spawn(async move {
+                if on_startup {
+                    if let Err(e) = scan_network(&scan_sender, false).await {
+                        error!("Scan hosts failed {e}");
+                    }
+                }
+                scan_schedule(scan_sender, config_rx).await;
+            }));
+        }
+
+        // Conflicts on gateways are confirmed by asking the MACs involved
+        for sender_mutex in sender_mutexes.iter() {
+            let prober = NetArpProber::new(
+                sender_mutex.clone(),
+                arp_cache_mutex.clone(),
+                events.clone(),
+            );
+            tasks.push(tokio::spawn(prober.event_handler()));
+        }
+
+        if cli.daemon {
+            if let Some(server) = server {
+                let server_cache = arp_cache_mutex.clone();
+                tasks.push(tokio::spawn(async move {
+                    if let Err(e) = server.serve(server_cache, events, sender_mutexes).await {
+                        error!("Event socket stopped: {e}");
+                    }
+                }));
+            }
+            if let Err(e) = daemon::wait_for_shutdown().await {
+                error!("Failed to install signal handlers: {e}");
+            }
+        } else {
+            let app = App::new(arp_cache_mutex.clone(), sender_mutexes);
+            tui::main_tui(app).await.unwrap();
+        }
+
+        for task in tasks {
+            task.abort();
+        }
+        let arp_cache = arp_cache_mutex.lock().await;
+        if let Err(e) = arp_cache.save_state() {
+            eprintln!("Failed to save binding database: {e}");
+        }
     });
+}
+
+fn runtime() -> Runtime {
+    Runtime::new().expect("Failed to start the tokio runtime")
+}
+
+/// Unprivileged side of the capture daemon: notifications and TUI fed from
+/// its event socket
+async fn connect(cli: &Cli, config: Config) {
+    let (events, alert_events) = broadcast::channel(EVENT_CAPACITY);
+    let (_config_tx, config_rx) = watch::channel(config.clone());
+    let alerter = Alerter::new(&config.alerts);
+    thread::Builder::new()
+        .name("alerts".to_string())
+        .spawn(move || alert::alert_handler(alerter, alert_events, config_rx))
+        .expect("Failed to start the alert thread");
+
+    let arp_cache_mutex = Arc::new(Mutex::new(ArpCache::empty(config.follow_update)));
+    let (commands_tx, commands_rx) = mpsc::channel(16);
+    let client_th = tokio::spawn(event_socket::event_client(
+        config.socket.path.clone(),
+        arp_cache_mutex.clone(),
+        events,
+        commands_rx,
+    ));
+
+    if cli.daemon {
+        if let Err(e) = daemon::wait_for_shutdown().await {
+            error!("Failed to install signal handlers: {e}");
+        }
+    } else {
+        let app = App::remote(arp_cache_mutex, commands_tx);
+        tui::main_tui(app).await.unwrap();
+    }
+    client_th.abort();
+}
 
-    tui::main_tui(app).await.unwrap();
-    listener_th.abort();
+fn list_interfaces(format: OutputFormat) {
+    for interface in NetArpWatcher::interfaces() {
+        let mac = interface.mac.map(|mac| mac.to_string());
+        let ips: Vec<String> = interface.ips.iter().map(|ip| ip.to_string()).collect();
+        match format {
+            OutputFormat::Text => println!(
+                "{:<16} {:<17} {}",
+                interface.name,
+                mac.as_deref().unwrap_or("-"),
+                ips.join(", ")
+            ),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "name": interface.name,
+                    "index": interface.index,
+                    "mac": mac,
+                    "ips": ips,
+                    "up": interface.is_up(),
+                    "loopback": interface.is_loopback(),
+                })
+            ),
+        }
+    }
 }
@@ -0,0 +1,232 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload, NLM_F_DUMP, NLM_F_REQUEST};
+use netlink_packet_route::{constants::*, neighbour::Nla, NeighbourMessage, RtnlMessage};
+use netlink_sys::{protocols::NETLINK_ROUTE, AsyncSocket, AsyncSocketExt, SocketAddr, TokioSocket};
+use nix::errno::Errno;
+use pnet::{datalink::NetworkInterface, util::MacAddr};
+
+use crate::arp_cache::*;
+
+/// States in which the kernel holds a usable link layer address
+const NUD_VALID: u16 =
+    NUD_PERMANENT | NUD_NOARP | NUD_REACHABLE | NUD_PROBE | NUD_STALE | NUD_DELAY;
+
+/// Follow the kernel neighbour table through rtnetlink so that changes made
+/// to this host's own ARP cache are seen even when the packet was missed
+pub struct NeighbourMonitor {
+    socket: TokioSocket,
+    ifindex: u32,
+    device: String,
+    arp_cache: ArpCacheMutex,
+}
+
+impl NeighbourMonitor {
+    pub fn new(interface: &NetworkInterface, arp_cache: ArpCacheMutex) -> std::io::Result<Self> {
+        let mut socket = TokioSocket::new(NETLINK_ROUTE)?;
+        socket.socket_mut().bind(&SocketAddr::new(0, 0))?;
+        socket.socket_mut().add_membership(RTNLGRP_NEIGH)?;
+        Ok(Self {
+            socket,
+            ifindex: interface.index,
+            device: interface.name.clone(),
+            arp_cache,
+        })
+    }
+
+    pub async fn event_handler(&mut self) -> Result<(), Box<dyn Error>> {
+        info!("Starting neighbour table monitor on {}", self.device);
+        loop {
+            let buf = match self.socket.recv_from_full().await {
+                Ok((buf, _)) => buf,
+                // Changes were dropped while the table churned
+                Err(e) if e.raw_os_error() == Some(Errno::ENOBUFS as i32) => {
+                    warn!("Missed neighbour changes on {}, resyncing", self.device);
+                    self.resync().await?;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            for message in parse_messages(&buf) {
+                match message.payload {
+                    NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(neighbour)) => {
+                        self.new_neighbour(neighbour).await
+                    }
+                    NetlinkPayload::InnerMessage(RtnlMessage::DelNeighbour(neighbour)) => {
+                        self.del_neighbour(neighbour).await
+                    }
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Dump the whole neighbour table, answered like changes
+    async fn resync(&self) -> std::io::Result<()> {
+        let mut request = NetlinkMessage::from(RtnlMessage::GetNeighbour(Default::default()));
+        request.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+        request.finalize();
+        let mut buf = vec![0; request.buffer_len()];
+        request.serialize(&mut buf);
+        self.socket.send_to(&buf, &SocketAddr::new(0, 0)).await?;
+        Ok(())
+    }
+
+    fn is_watched(&self, neighbour: &NeighbourMessage) -> bool {
+        let family = neighbour.header.family as u16;
+        (family == AF_INET || family == AF_INET6) && neighbour.header.ifindex == self.ifindex
+    }
+
+    async fn new_neighbour(&mut self, neighbour: NeighbourMessage) {
+        if !self.is_watched(&neighbour) {
+            return;
+        }
+        let state = neighbour.header.state;
+        let (ip, mac) = match neighbour_addresses(&neighbour) {
+            // Multicast MACs are mapped from the IP, never learned
+            (Some(ip), _) if ip.is_multicast() => return,
+            (Some(ip), mac) => (ip, mac),
+            (None, _) => return,
+        };
+        info!(
+            "Kernel neighbour {} is {} at {}",
+            ip,
+            state_name(state),
+            mac.map_or("<none>".to_string(), |mac| mac.to_string())
+        );
+
+        let mac = match mac {
+            Some(mac) if state & NUD_VALID != 0 => mac,
+            _ => {
+                if state & NUD_FAILED != 0 {
+                    warn!("Kernel failed to resolve {}", ip);
+                    self.arp_cache.lock().await.unresolved(&self.device, ip);
+                }
+                return;
+            }
+        };
+
+        let mut flags = ArpFlags::COMPLETE;
+        if state & NUD_PERMANENT != 0 {
+            flags |= ArpFlags::PERMANENT;
+        }
+        let entry = ArpEntry::new(ip, mac, BindingSource::KernelCache, Utc::now())
+            .with_device(&self.device)
+            .with_flags(ArpFlags(flags));
+        let mut arp_cache = self.arp_cache.lock().await;
+        arp_cache.update(entry);
+    }
+
+    async fn del_neighbour(&self, neighbour: NeighbourMessage) {
+        if !self.is_watched(&neighbour) {
+            return;
+        }
+        if let (Some(ip), _) = neighbour_addresses(&neighbour) {
+            debug!("Kernel neighbour {} removed", ip);
+            self.arp_cache.lock().await.unresolved(&self.device, ip);
+        }
+    }
+}
+
+/// Messages of a datagram, those that cannot be parsed are skipped
+fn parse_messages(buf: &[u8]) -> Vec<NetlinkMessage<RtnlMessage>> {
+    let mut messages = vec![];
+    let mut offset = 0;
+    // Every message starts with its length
+    while let Some(len) = buf.get(offset..offset + 4) {
+        let len = u32::from_ne_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        if len == 0 || offset + len > buf.len() {
+            break;
+        }
+        match NetlinkMessage::<RtnlMessage>::deserialize(&buf[offset..offset + len]) {
+            Ok(message) => messages.push(message),
+            Err(e) => debug!("Skipping unparsable neighbour message: {e}"),
+        }
+        // Messages are 4 bytes aligned
+        offset += (len + 3) & !3;
+    }
+    messages
+}
+
+fn neighbour_addresses(neighbour: &NeighbourMessage) -> (Option<IpAddr>, Option<MacAddr>) {
+    let mut ip = None;
+    let mut mac = None;
+    for nla in neighbour.nlas.iter() {
+        match nla {
+            Nla::Destination(bytes) if bytes.len() == 4 => {
+                ip = Some(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).into());
+            }
+            Nla::Destination(bytes) if bytes.len() == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(bytes);
+                ip = Some(Ipv6Addr::from(octets).into());
+            }
+            Nla::LinkLocalAddress(bytes) if bytes.len() == 6 => {
+                mac = Some(MacAddr::new(
+                    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5],
+                ));
+            }
+            _ => continue,
+        }
+    }
+    (ip, mac)
+}
+
+fn state_name(state: u16) -> &'static str {
+    match state {
+        NUD_INCOMPLETE => "INCOMPLETE",
+        NUD_REACHABLE => "REACHABLE",
+        NUD_STALE => "STALE",
+        NUD_DELAY => "DELAY",
+        NUD_PROBE => "PROBE",
+        NUD_FAILED => "FAILED",
+        NUD_NOARP => "NOARP",
+        NUD_PERMANENT => "PERMANENT",
+        NUD_NONE => "NONE",
+        _ => "UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(message: RtnlMessage) -> Vec<u8> {
+        let mut message = NetlinkMessage::from(message);
+        message.finalize();
+        let mut buf = vec![0; message.buffer_len()];
+        message.serialize(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn unparsable_messages_skipped() {
+        let mut neighbour = NeighbourMessage::default();
+        neighbour.header.ifindex = 3;
+        neighbour.nlas.push(Nla::Destination(vec![10, 0, 0, 2]));
+        // Too short for a neighbour header
+        let mut buf = 20u32.to_ne_bytes().to_vec();
+        buf.extend(RTM_NEWNEIGH.to_ne_bytes());
+        buf.extend([0; 14]);
+        buf.extend(serialize(RtnlMessage::NewNeighbour(neighbour.clone())));
+        buf.extend(serialize(RtnlMessage::DelNeighbour(neighbour)));
+
+        let messages = parse_messages(&buf);
+        assert_eq!(messages.len(), 2);
+        let NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(parsed)) = &messages[0].payload
+        else {
+            panic!("not a new neighbour: {:?}", messages[0]);
+        };
+        assert_eq!(parsed.header.ifindex, 3);
+        assert_eq!(
+            neighbour_addresses(parsed),
+            (Some(Ipv4Addr::new(10, 0, 0, 2).into()), None)
+        );
+        assert!(parse_messages(&buf[..10]).is_empty());
+    }
+}
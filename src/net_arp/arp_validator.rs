@@ -0,0 +1,415 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use pnet::packet::{
+    arp::{ArpHardwareTypes, ArpOperations, ArpPacket},
+    ethernet::{EtherTypes, EthernetPacket},
+};
+
+use crate::{arp_cache::within, event::Anomaly};
+
+use super::*;
+
+/// A kind of anomaly seen again from the same sender is only reported once
+/// per interval, along with how many were seen
+const ANOMALY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Senders reported per kind and interval, forged source MACs must not turn
+/// into as many alerts
+const MAX_REPORTED_SENDERS: usize = 16;
+
+impl ArpValidator {
+    pub fn new(networks: Vec<IpNetwork>) -> Self {
+        Self {
+            networks: networks
+                .into_iter()
+                .filter(|network| network.is_ipv4())
+                .collect(),
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Problems found in an ARP packet, and whether it still describes a
+    /// binding worth recording
+    pub fn inspect(&self, ethernet: &EthernetPacket, arp: &ArpPacket) -> (Vec<Anomaly>, bool) {
+        let mut anomalies = vec![];
+        if arp.get_hardware_type() != ArpHardwareTypes::Ethernet {
+            anomalies.push(Anomaly::HardwareType {
+                hw_type: arp.get_hardware_type().0,
+            });
+        }
+        if arp.get_protocol_type() != EtherTypes::Ipv4 {
+            anomalies.push(Anomaly::ProtocolType {
+                protocol: arp.get_protocol_type().0,
+            });
+        }
+        if arp.get_hw_addr_len() != 6 || arp.get_proto_addr_len() != 4 {
+            anomalies.push(Anomaly::AddressLength {
+                hw_len: arp.get_hw_addr_len(),
+                proto_len: arp.get_proto_addr_len(),
+            });
+        }
+        let operation = arp.get_operation();
+        if operation != ArpOperations::Request && operation != ArpOperations::Reply {
+            anomalies.push(Anomaly::Opcode {
+                opcode: operation.0,
+            });
+        }
+        // Nothing else can be trusted in such a packet
+        if !anomalies.is_empty() {
+            return (anomalies, false);
+        }
+
+        let sender_mac = arp.get_sender_hw_addr();
+        let sender_ip = arp.get_sender_proto_addr();
+        let target_ip = arp.get_target_proto_addr();
+        let mut usable = true;
+        if ethernet.get_source() != sender_mac {
+            anomalies.push(Anomaly::SenderMismatch {
+                ethernet: ethernet.get_source(),
+                arp: sender_mac,
+            });
+        }
+        // Nobody can be reached at such an address
+        if sender_mac.0 & 0x01 != 0 {
+            anomalies.push(Anomaly::MulticastSender { mac: sender_mac });
+            usable = false;
+        }
+        if operation == ArpOperations::Reply && ethernet.get_destination() == MacAddr::broadcast() {
+            anomalies.push(Anomaly::BroadcastReply { mac: sender_mac });
+        }
+
+        // RFC 5227 probes are the only packets without a sender IP, replies
+        // to them the only ones without a target IP
+        let probe = operation == ArpOperations::Request && sender_ip.is_unspecified();
+        let sender_martian = is_martian(sender_ip) && !probe;
+        if sender_martian {
+            anomalies.push(Anomaly::Martian {
+                ip: sender_ip.into(),
+            });
+            usable = false;
+        }
+        if is_martian(target_ip) && !target_ip.is_unspecified() {
+            anomalies.push(Anomaly::Martian {
+                ip: target_ip.into(),
+            });
+        }
+        if !sender_martian
+            && !probe
+            && !self.networks.is_empty()
+            && !self
+                .networks
+                .iter()
+                .any(|network| network.contains(sender_ip.into()))
+        {
+            anomalies.push(Anomaly::ForeignSender { ip: sender_ip });
+        }
+        (anomalies, usable)
+    }
+
+    /// Problems found in a Neighbor Discovery message, and whether it still
+    /// describes a binding worth recording
+    pub fn inspect_ndp(
+        &self,
+        ethernet: &EthernetPacket,
+        message: &NdpMessage,
+    ) -> (Vec<Anomaly>, bool) {
+        // Forged off link, nothing in it can be trusted
+        if message.hop_limit != 255 {
+            let anomaly = Anomaly::HopLimit {
+                hop_limit: message.hop_limit,
+            };
+            return (vec![anomaly], false);
+        }
+
+        let mut anomalies = vec![];
+        let mut usable = true;
+        if let Some(mac) = message.mac {
+            if ethernet.get_source() != mac {
+                anomalies.push(Anomaly::SenderMismatch {
+                    ethernet: ethernet.get_source(),
+                    arp: mac,
+                });
+            }
+            if mac.0 & 0x01 != 0 {
+                anomalies.push(Anomaly::MulticastSender { mac });
+                usable = false;
+            }
+        }
+        // Duplicate address detection is the only message without a source
+        let dad = matches!(message.kind, NdpKind::NeighborSolicit { .. })
+            && message.source.is_unspecified();
+        if is_martian_v6(message.source) && !dad {
+            anomalies.push(Anomaly::Martian {
+                ip: message.source.into(),
+            });
+            usable = false;
+        }
+        match message.kind {
+            NdpKind::NeighborSolicit { target } | NdpKind::NeighborAdvert { target, .. }
+                if is_martian_v6(target) =>
+            {
+                anomalies.push(Anomaly::Martian { ip: target.into() });
+                usable = false;
+            }
+            _ => (),
+        }
+        (anomalies, usable)
+    }
+
+    /// Count `anomaly` sent by `sender`, returns how many of that kind were
+    /// seen so far when it is worth reporting
+    pub fn count(&mut self, anomaly: &Anomaly, sender: MacAddr, at: DateTime<Utc>) -> Option<u64> {
+        let counter = self.counters.entry(anomaly.kind()).or_default();
+        counter.count += 1;
+        counter
+            .reported
+            .retain(|_, reported| within(*reported, at, ANOMALY_REPORT_INTERVAL));
+        if counter.reported.contains_key(&sender) || counter.reported.len() >= MAX_REPORTED_SENDERS
+        {
+            return None;
+        }
+        counter.reported.insert(sender, at);
+        Some(counter.count)
+    }
+}
+
+/// Addresses that never belong on the wire: unspecified, loopback,
+/// multicast, broadcast and reserved
+fn is_martian(ip: Ipv4Addr) -> bool {
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.octets()[0] >= 240
+}
+
+/// IPv6 addresses that never belong to a neighbour: unspecified, loopback
+/// and multicast
+fn is_martian_v6(ip: Ipv6Addr) -> bool {
+    ip.is_unspecified() || ip.is_loopback() || ip.is_multicast()
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::arp::ArpOperation;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    /// ARP packet from `sender` at `sender_ip` in a frame from `source`
+    fn frame(
+        operation: ArpOperation,
+        source: MacAddr,
+        destination: MacAddr,
+        sender: MacAddr,
+        sender_ip: Ipv4Addr,
+    ) -> Vec<u8> {
+        let target = (MacAddr::zero(), Ipv4Addr::new(10, 0, 0, 1));
+        arp_frame(operation, source, destination, (sender, sender_ip), target)
+    }
+
+    fn request(sender: MacAddr, sender_ip: Ipv4Addr) -> Vec<u8> {
+        frame(
+            ArpOperations::Request,
+            sender,
+            MacAddr::broadcast(),
+            sender,
+            sender_ip,
+        )
+    }
+
+    fn inspect(validator: &ArpValidator, data: &[u8]) -> (Vec<Anomaly>, bool) {
+        let ethernet = EthernetPacket::new(data).unwrap();
+        let arp = ArpPacket::new(&data[14..]).unwrap();
+        validator.inspect(&ethernet, &arp)
+    }
+
+    fn validator() -> ArpValidator {
+        ArpValidator::new(vec!["10.0.0.0/24".parse().unwrap()])
+    }
+
+    #[test]
+    fn valid_request() {
+        let data = request(mac_addr(2), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(inspect(&validator(), &data), (vec![], true));
+    }
+
+    #[test]
+    fn sender_mismatch() {
+        let data = frame(
+            ArpOperations::Request,
+            mac_addr(3),
+            MacAddr::broadcast(),
+            mac_addr(2),
+            Ipv4Addr::new(10, 0, 0, 2),
+        );
+        let anomaly = Anomaly::SenderMismatch {
+            ethernet: mac_addr(3),
+            arp: mac_addr(2),
+        };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], true));
+    }
+
+    #[test]
+    fn multicast_sender_unusable() {
+        let sender = MacAddr::new(1, 0, 0x5e, 0, 0, 1);
+        let data = request(sender, Ipv4Addr::new(10, 0, 0, 2));
+        let anomaly = Anomaly::MulticastSender { mac: sender };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], false));
+    }
+
+    #[test]
+    fn broadcast_reply() {
+        let data = frame(
+            ArpOperations::Reply,
+            mac_addr(2),
+            MacAddr::broadcast(),
+            mac_addr(2),
+            Ipv4Addr::new(10, 0, 0, 2),
+        );
+        let anomaly = Anomaly::BroadcastReply { mac: mac_addr(2) };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], true));
+    }
+
+    #[test]
+    fn martian_sender_unusable() {
+        let data = request(mac_addr(2), Ipv4Addr::new(127, 0, 0, 1));
+        let anomaly = Anomaly::Martian {
+            ip: Ipv4Addr::new(127, 0, 0, 1).into(),
+        };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], false));
+    }
+
+    #[test]
+    fn probe_without_sender_ip() {
+        let data = request(mac_addr(2), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(inspect(&validator(), &data), (vec![], true));
+    }
+
+    #[test]
+    fn foreign_sender() {
+        let data = request(mac_addr(2), Ipv4Addr::new(192, 168, 1, 2));
+        let anomaly = Anomaly::ForeignSender {
+            ip: Ipv4Addr::new(192, 168, 1, 2),
+        };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], true));
+        let anywhere = ArpValidator::new(vec![]);
+        assert_eq!(inspect(&anywhere, &data), (vec![], true));
+    }
+
+    #[test]
+    fn unknown_opcode_unusable() {
+        let data = frame(
+            ArpOperation(3),
+            mac_addr(3),
+            MacAddr::broadcast(),
+            mac_addr(2),
+            Ipv4Addr::new(127, 0, 0, 1),
+        );
+        let anomaly = Anomaly::Opcode { opcode: 3 };
+        assert_eq!(inspect(&validator(), &data), (vec![anomaly], false));
+    }
+
+    #[test]
+    fn counted_by_kind_reported_by_sender() {
+        let anomaly = Anomaly::Malformed { length: 10 };
+        let mut validator = validator();
+        assert_eq!(validator.count(&anomaly, mac_addr(1), at(0)), Some(1));
+        assert_eq!(validator.count(&anomaly, mac_addr(1), at(1)), None);
+        assert_eq!(validator.count(&anomaly, mac_addr(2), at(2)), Some(3));
+        let other = Anomaly::HopLimit { hop_limit: 64 };
+        assert_eq!(validator.count(&other, mac_addr(1), at(3)), Some(1));
+        assert_eq!(validator.count(&anomaly, mac_addr(1), at(60)), Some(4));
+    }
+
+    #[test]
+    fn reported_senders_capped() {
+        let anomaly = Anomaly::Malformed { length: 10 };
+        let mut validator = validator();
+        let reported = (0..=MAX_REPORTED_SENDERS as u8)
+            .filter_map(|last| validator.count(&anomaly, mac_addr(last), at(0)))
+            .count();
+        assert_eq!(reported, MAX_REPORTED_SENDERS);
+    }
+
+    /// Neighbor Solicitation from `source` in a frame from mac_addr(1)
+    fn solicitation(source: &str, hop_limit: u8) -> NdpMessage {
+        NdpMessage {
+            source: source.parse().unwrap(),
+            destination: "ff02::1:ff00:2".parse().unwrap(),
+            hop_limit,
+            mac: Some(mac_addr(1)),
+            kind: NdpKind::NeighborSolicit {
+                target: "fe80::2".parse().unwrap(),
+            },
+        }
+    }
+
+    fn inspect_ndp(message: &NdpMessage) -> (Vec<Anomaly>, bool) {
+        let data = request(mac_addr(1), Ipv4Addr::new(10, 0, 0, 1));
+        validator().inspect_ndp(&EthernetPacket::new(&data).unwrap(), message)
+    }
+
+    #[test]
+    fn valid_solicitation() {
+        assert_eq!(inspect_ndp(&solicitation("fe80::1", 255)), (vec![], true));
+    }
+
+    #[test]
+    fn ndp_forwarded() {
+        let anomaly = Anomaly::HopLimit { hop_limit: 64 };
+        assert_eq!(
+            inspect_ndp(&solicitation("fe80::1", 64)),
+            (vec![anomaly], false)
+        );
+    }
+
+    #[test]
+    fn ndp_martian() {
+        let ip: Ipv6Addr = "ff02::1".parse().unwrap();
+        let anomaly = Anomaly::Martian { ip: ip.into() };
+        assert_eq!(
+            inspect_ndp(&solicitation("ff02::1", 255)),
+            (vec![anomaly], false)
+        );
+
+        let mut advert = solicitation("fe80::1", 255);
+        advert.kind = NdpKind::NeighborAdvert {
+            target: "::1".parse().unwrap(),
+            solicited: false,
+        };
+        let ip: Ipv6Addr = "::1".parse().unwrap();
+        let anomaly = Anomaly::Martian { ip: ip.into() };
+        assert_eq!(inspect_ndp(&advert), (vec![anomaly], false));
+    }
+
+    #[test]
+    fn duplicate_address_detection() {
+        assert_eq!(inspect_ndp(&solicitation("::", 255)), (vec![], true));
+
+        let mut advert = solicitation("::", 255);
+        advert.kind = NdpKind::NeighborAdvert {
+            target: "fe80::2".parse().unwrap(),
+            solicited: false,
+        };
+        let anomaly = Anomaly::Martian {
+            ip: Ipv6Addr::UNSPECIFIED.into(),
+        };
+        assert_eq!(inspect_ndp(&advert), (vec![anomaly], false));
+    }
+
+    #[test]
+    fn ndp_sender_mismatch() {
+        let mut message = solicitation("fe80::1", 255);
+        message.mac = Some(mac_addr(2));
+        let anomaly = Anomaly::SenderMismatch {
+            ethernet: mac_addr(1),
+            arp: mac_addr(2),
+        };
+        assert_eq!(inspect_ndp(&message), (vec![anomaly], true));
+    }
+}
@@ -1,32 +1,176 @@
-use std::{net::Ipv4Addr, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
 
+use chrono::{DateTime, Utc};
 use pnet::{
     datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface},
-    ipnetwork::IpNetwork,
+    ipnetwork::{IpNetwork, Ipv4Network},
     util::MacAddr,
 };
 use tokio::sync::Mutex;
 
-use crate::arp_cache::ArpCacheMutex;
+use crate::{arp_cache::ArpCacheMutex, event::EventSender};
 
+pub mod arp_validator;
+pub mod ndp;
 pub mod net_arp_listener;
+pub mod net_arp_prober;
 pub mod net_arp_sender;
 pub mod net_arp_watcher;
+pub mod packet_source;
+pub mod pcap_source;
+pub mod pending_requests;
 
 pub type NetArpSenderMutex = Arc<Mutex<NetArpSender>>;
+/// Shared by the listener and the sender of an interface, never held
+/// across an await
+pub type PendingRequestsMutex = Arc<std::sync::Mutex<PendingRequests>>;
 
 pub struct NetArpWatcher {
     interface: NetworkInterface,
+    requests: PendingRequestsMutex,
+}
+
+/// ARP requests and Neighbor Solicitations seen on the wire or sent lately,
+/// by requester MAC and requested IP, so that replies nobody asked for stand
+/// out
+#[derive(Debug, Default)]
+pub struct PendingRequests {
+    requests: HashMap<(MacAddr, IpAddr), DateTime<Utc>>,
+    /// Last time expired requests were dropped
+    swept: DateTime<Utc>,
 }
 
 pub struct NetArpSender {
     tx: Box<dyn DataLinkSender>,
     source_mac: MacAddr,
-    source_ip: Ipv4Addr,
-    network_addr: IpNetwork,
+    /// IPv4 networks of the interface, the primary address first
+    networks: Vec<Ipv4Network>,
+    /// Scanned instead of `networks` when set, those on `networks` only
+    scan_targets: Vec<IpNetwork>,
+    /// Ranges over this many addresses need confirming, 0 for no limit
+    max_scan_hosts: u64,
+    device: String,
+    /// Where to report finished scans
+    pub events: Option<EventSender>,
+    requests: PendingRequestsMutex,
+}
+
+/// Confirms conflicting bindings on one interface by asking the MACs
+/// involved directly
+#[derive(Clone)]
+pub struct NetArpProber {
+    sender: NetArpSenderMutex,
+    arp_cache: ArpCacheMutex,
+    events: EventSender,
 }
 
 pub struct NetArpListener {
-    rx: Box<dyn DataLinkReceiver>,
+    source: Box<dyn PacketSource>,
     arp_cache: ArpCacheMutex,
+    local_mac: Option<MacAddr>,
+    device: String,
+    requests: PendingRequestsMutex,
+    validator: ArpValidator,
+}
+
+/// Sanity checks of ARP frames, counting every kind of problem found
+#[derive(Debug, Default)]
+pub struct ArpValidator {
+    /// Subnets of the interface, senders outside them are reported
+    networks: Vec<IpNetwork>,
+    /// By kind of anomaly
+    counters: HashMap<&'static str, AnomalyCounter>,
+}
+
+/// Neighbor Discovery message carrying a binding, RFC 4861
+#[derive(Debug, Clone, PartialEq)]
+pub struct NdpMessage {
+    /// IPv6 source, unspecified for duplicate address detection
+    pub source: Ipv6Addr,
+    pub destination: Ipv6Addr,
+    pub hop_limit: u8,
+    /// Source or target link-layer address option
+    pub mac: Option<MacAddr>,
+    pub kind: NdpKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NdpKind {
+    NeighborSolicit {
+        target: Ipv6Addr,
+    },
+    NeighborAdvert {
+        target: Ipv6Addr,
+        /// Answers a solicitation, unsolicited ones announce a binding
+        solicited: bool,
+    },
+    RouterAdvert {
+        /// Seconds the router may be used as default router
+        lifetime: u16,
+        prefixes: Vec<IpNetwork>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct AnomalyCounter {
+    /// Seen from every sender
+    count: u64,
+    /// Last report by sender MAC
+    reported: HashMap<MacAddr, DateTime<Utc>>,
+}
+
+/// Raw Ethernet frame along with the time it was captured
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub timestamp: DateTime<Utc>,
+    pub data: Vec<u8>,
+}
+
+/// Anything able to hand Ethernet frames to a `NetArpListener`
+pub trait PacketSource: Send {
+    /// Next captured frame, `Ok(None)` once the source is exhausted
+    fn next_frame(&mut self) -> std::io::Result<Option<Frame>>;
+}
+
+/// Live capture on a network interface through a pnet datalink channel
+pub struct LivePacketSource {
+    rx: Box<dyn DataLinkReceiver>,
+}
+
+/// Frames queued in memory, handy for tests and synthetic traffic
+#[derive(Debug, Default)]
+pub struct MemoryPacketSource {
+    frames: VecDeque<Frame>,
+}
+
+/// Offline replay of a pcap or pcapng capture file
+pub struct PcapPacketSource {
+    reader: Box<dyn Read + Send>,
+    format: PcapFormat,
+    big_endian: bool,
+    done: bool,
+}
+
+enum PcapFormat {
+    /// Classic libpcap file, with the timestamp resolution, link type and
+    /// snapshot length taken from the global header
+    Pcap {
+        nanos: bool,
+        link_type: u32,
+        snaplen: usize,
+    },
+    /// pcapng file, link type and timestamp resolution of every interface
+    /// described so far in the current section
+    PcapNg { interfaces: Vec<PcapNgInterface> },
+}
+
+struct PcapNgInterface {
+    link_type: u16,
+    /// Timestamp units per second
+    ts_resolution: u64,
 }
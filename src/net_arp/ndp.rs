@@ -0,0 +1,240 @@
+use pnet::packet::{
+    ethernet::EthernetPacket,
+    icmpv6::{
+        ndp::{
+            NdpOptionType, NdpOptionTypes, NeighborAdvertFlags, NeighborAdvertPacket,
+            NeighborSolicitPacket, RouterAdvertPacket,
+        },
+        Icmpv6Type, Icmpv6Types,
+    },
+    ip::IpNextHeaderProtocols,
+    ipv6::Ipv6Packet,
+    Packet,
+};
+
+use crate::event::Anomaly;
+
+use super::*;
+
+/// Fixed part of Neighbor Solicitations and Advertisements, options follow
+const NEIGHBOR_HEADER_LEN: usize = 24;
+const ROUTER_ADVERT_HEADER_LEN: usize = 16;
+
+impl NdpMessage {
+    /// Neighbor Discovery message carried by an IPv6 frame, `Ok(None)` for
+    /// any other IPv6 traffic
+    pub fn parse(ethernet: &EthernetPacket) -> Result<Option<Self>, Anomaly> {
+        let malformed = Anomaly::Malformed {
+            length: ethernet.payload().len(),
+        };
+        let ipv6 = Ipv6Packet::new(ethernet.payload()).ok_or(malformed.clone())?;
+        // Extension headers are not expected in front of NDP
+        if ipv6.get_next_header() != IpNextHeaderProtocols::Icmpv6 {
+            return Ok(None);
+        }
+        let icmp = ipv6.payload();
+        let (icmp_type, icmp_code) = match icmp {
+            [icmp_type, icmp_code, ..] => (Icmpv6Type(*icmp_type), *icmp_code),
+            _ => return Err(malformed),
+        };
+        let header_len = match icmp_type {
+            Icmpv6Types::NeighborSolicit | Icmpv6Types::NeighborAdvert => NEIGHBOR_HEADER_LEN,
+            Icmpv6Types::RouterAdvert => ROUTER_ADVERT_HEADER_LEN,
+            _ => return Ok(None),
+        };
+        // RFC 4861 receivers silently discard other codes
+        if icmp_code != 0 {
+            return Ok(None);
+        }
+        if icmp.len() < header_len {
+            return Err(malformed);
+        }
+        let options = parse_options(&icmp[header_len..]).ok_or(malformed.clone())?;
+        let link_layer = |option_type| {
+            options
+                .iter()
+                .find(|(kind, data)| *kind == option_type && data.len() >= 6)
+                .map(|(_, data)| MacAddr::new(data[0], data[1], data[2], data[3], data[4], data[5]))
+        };
+
+        let (kind, mac) = match icmp_type {
+            Icmpv6Types::NeighborSolicit => {
+                let packet = NeighborSolicitPacket::new(icmp).ok_or(malformed)?;
+                let kind = NdpKind::NeighborSolicit {
+                    target: packet.get_target_addr(),
+                };
+                (kind, link_layer(NdpOptionTypes::SourceLLAddr))
+            }
+            Icmpv6Types::NeighborAdvert => {
+                let packet = NeighborAdvertPacket::new(icmp).ok_or(malformed)?;
+                let kind = NdpKind::NeighborAdvert {
+                    target: packet.get_target_addr(),
+                    solicited: packet.get_flags() & NeighborAdvertFlags::Solicited != 0,
+                };
+                (kind, link_layer(NdpOptionTypes::TargetLLAddr))
+            }
+            _ => {
+                let packet = RouterAdvertPacket::new(icmp).ok_or(malformed)?;
+                let prefixes = options
+                    .iter()
+                    .filter(|(kind, data)| {
+                        *kind == NdpOptionTypes::PrefixInformation && data.len() >= 30
+                    })
+                    .filter_map(|(_, data)| {
+                        let mut prefix = [0u8; 16];
+                        prefix.copy_from_slice(&data[14..30]);
+                        IpNetwork::new(Ipv6Addr::from(prefix).into(), data[0]).ok()
+                    })
+                    .collect();
+                let kind = NdpKind::RouterAdvert {
+                    lifetime: packet.get_lifetime(),
+                    prefixes,
+                };
+                (kind, link_layer(NdpOptionTypes::SourceLLAddr))
+            }
+        };
+        Ok(Some(Self {
+            source: ipv6.get_source(),
+            destination: ipv6.get_destination(),
+            hop_limit: ipv6.get_hop_limit(),
+            mac,
+            kind,
+        }))
+    }
+}
+
+/// Type and data of every option, `None` when an option overruns the packet
+/// or has a zero length, RFC 4861 says to discard such packets
+fn parse_options(mut bytes: &[u8]) -> Option<Vec<(NdpOptionType, &[u8])>> {
+    let mut options = vec![];
+    while !bytes.is_empty() {
+        let (option_type, length) = match bytes {
+            [option_type, length, ..] => (NdpOptionType(*option_type), *length as usize * 8),
+            _ => return None,
+        };
+        if length == 0 || length > bytes.len() {
+            return None;
+        }
+        options.push((option_type, &bytes[2..length]));
+        bytes = &bytes[length..];
+    }
+    Some(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+    /// Ethernet frame carrying `icmp` from fe80::1 to ff02::1
+    fn frame(next_header: u8, icmp: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xff; 6];
+        data.extend(SOURCE);
+        data.extend([0x86, 0xdd, 0x60, 0, 0, 0]);
+        data.extend((icmp.len() as u16).to_be_bytes());
+        data.extend([next_header, 255]);
+        data.extend("fe80::1".parse::<Ipv6Addr>().unwrap().octets());
+        data.extend("ff02::1".parse::<Ipv6Addr>().unwrap().octets());
+        data.extend(icmp);
+        data
+    }
+
+    /// Neighbor Solicitation or Advertisement of fe80::2, then `options`
+    fn neighbor(icmp_type: u8, flags: u8, options: &[u8]) -> Vec<u8> {
+        let mut icmp = vec![icmp_type, 0, 0, 0, flags, 0, 0, 0];
+        icmp.extend("fe80::2".parse::<Ipv6Addr>().unwrap().octets());
+        icmp.extend(options);
+        frame(58, &icmp)
+    }
+
+    fn link_layer(option_type: u8) -> Vec<u8> {
+        let mut option = vec![option_type, 1];
+        option.extend(SOURCE);
+        option
+    }
+
+    fn parse(data: &[u8]) -> Result<Option<NdpMessage>, Anomaly> {
+        NdpMessage::parse(&EthernetPacket::new(data).unwrap())
+    }
+
+    #[test]
+    fn neighbor_solicitation() {
+        let message = parse(&neighbor(135, 0, &link_layer(1))).unwrap().unwrap();
+        assert_eq!(message.source, "fe80::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(message.hop_limit, 255);
+        assert_eq!(message.mac, Some(MacAddr::from(SOURCE)));
+        assert_eq!(
+            message.kind,
+            NdpKind::NeighborSolicit {
+                target: "fe80::2".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn neighbor_advertisement() {
+        let message = parse(&neighbor(136, 0x40, &link_layer(2)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.mac, Some(MacAddr::from(SOURCE)));
+        assert_eq!(
+            message.kind,
+            NdpKind::NeighborAdvert {
+                target: "fe80::2".parse().unwrap(),
+                solicited: true,
+            }
+        );
+        // Source link-layer option in an advertisement is not the target's
+        let message = parse(&neighbor(136, 0, &link_layer(1))).unwrap().unwrap();
+        assert_eq!(message.mac, None);
+    }
+
+    #[test]
+    fn router_advertisement() {
+        let mut icmp = vec![134, 0, 0, 0, 64, 0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend(link_layer(1));
+        icmp.extend([3, 4, 64, 0xc0]);
+        icmp.extend([0; 12]);
+        icmp.extend("2001:db8::".parse::<Ipv6Addr>().unwrap().octets());
+        let message = parse(&frame(58, &icmp)).unwrap().unwrap();
+        assert_eq!(message.mac, Some(MacAddr::from(SOURCE)));
+        assert_eq!(
+            message.kind,
+            NdpKind::RouterAdvert {
+                lifetime: 1800,
+                prefixes: vec!["2001:db8::/64".parse().unwrap()],
+            }
+        );
+    }
+
+    #[test]
+    fn bad_option_length() {
+        let length = neighbor(135, 0, &[]).len() - 14;
+        let malformed = Err(Anomaly::Malformed { length: length + 8 });
+        assert_eq!(
+            parse(&neighbor(135, 0, &[1, 0, 0, 0, 0, 0, 0, 0])),
+            malformed
+        );
+        assert_eq!(
+            parse(&neighbor(135, 0, &[1, 2, 0, 0, 0, 0, 0, 0])),
+            malformed
+        );
+        assert_eq!(
+            parse(&neighbor(135, 0, &[1])),
+            Err(Anomaly::Malformed { length: length + 1 })
+        );
+    }
+
+    #[test]
+    fn not_neighbor_discovery() {
+        // Echo request, then UDP
+        assert_eq!(parse(&frame(58, &[128, 0, 0, 0, 0, 0, 0, 0])), Ok(None));
+        assert_eq!(parse(&frame(17, &[0; 8])), Ok(None));
+        // Truncated fixed part
+        assert_eq!(
+            parse(&frame(58, &[135, 0, 0, 0])),
+            Err(Anomaly::Malformed { length: 44 })
+        );
+    }
+}
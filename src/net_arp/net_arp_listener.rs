@@ -1,9 +1,8 @@
-use core::panic;
-use std::error::Error;
+use std::{error::Error, net::IpAddr};
 
-use log::info;
+use log::{debug, info, warn};
 use pnet::{
-    datalink::{self, Channel::Ethernet, NetworkInterface},
+    datalink::NetworkInterface,
     packet::{
         arp::{ArpOperations, ArpPacket},
         ethernet::{EtherTypes, EthernetPacket},
@@ -11,59 +10,434 @@ use pnet::{
     },
 };
 
-use crate::arp_cache::*;
+use crate::{
+    arp_cache::*,
+    event::{Anomaly, ArpEvent, EventContext},
+};
 
-use super::NetArpListener;
+use super::*;
 
 impl NetArpListener {
-    pub fn new(interface: &NetworkInterface, arp_cache: ArpCacheMutex) -> Self {
-        let (_, rx) = match datalink::channel(interface, Default::default()) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unhandled channel type"),
-            Err(e) => panic!(
-                "An error occurred when creating the datalink sender channel: {}",
-                e
-            ),
-        };
-        Self { rx, arp_cache }
+    pub fn new(
+        interface: &NetworkInterface,
+        arp_cache: ArpCacheMutex,
+        requests: PendingRequestsMutex,
+    ) -> Self {
+        let mut listener = Self::with_source(Box::new(LivePacketSource::new(interface)), arp_cache);
+        listener.local_mac = interface.mac;
+        listener.device = interface.name.clone();
+        listener.requests = requests;
+        listener.validator = ArpValidator::new(interface.ips.clone());
+        listener
+    }
+
+    pub fn with_source(source: Box<dyn PacketSource>, arp_cache: ArpCacheMutex) -> Self {
+        Self {
+            source,
+            arp_cache,
+            local_mac: None,
+            device: String::new(),
+            requests: Default::default(),
+            validator: Default::default(),
+        }
     }
 
     pub async fn packet_handler(&mut self) -> Result<(), Box<dyn Error>> {
         info!("Starting packet handler");
         loop {
-            if let Ok(buf) = self.rx.next() {
-                let ethernet_packet = match EthernetPacket::new(buf) {
-                    Some(ethernet_packet) => ethernet_packet,
-                    None => continue,
-                };
-
-                if ethernet_packet.get_ethertype() != EtherTypes::Arp {
-                    continue;
+            match self.next_frame() {
+                Ok(Some(frame)) => {
+                    self.handle_frame(&frame).await;
                 }
+                Ok(None) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => (),
+                Err(e) => debug!("Failed to read frame: {e}"),
+            }
+        }
+        info!("Packet source exhausted");
+        Ok(())
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn next_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        self.source.next_frame()
+    }
+
+    /// Feed a single Ethernet frame to the ARP cache, returns the cache
+    /// update result when the frame carried a binding, probes carry none
+    pub async fn handle_frame(
+        &mut self,
+        frame: &Frame,
+    ) -> Option<(ArpEntry, ArpCacheUpdateResult)> {
+        let ethernet_packet = EthernetPacket::new(&frame.data)?;
+
+        match ethernet_packet.get_ethertype() {
+            EtherTypes::Arp => self.handle_arp(frame, &ethernet_packet).await,
+            EtherTypes::Ipv6 => self.handle_ndp(frame, &ethernet_packet).await,
+            _ => None,
+        }
+    }
+
+    async fn handle_arp(
+        &mut self,
+        frame: &Frame,
+        ethernet_packet: &EthernetPacket<'_>,
+    ) -> Option<(ArpEntry, ArpCacheUpdateResult)> {
+        self.arp_cache
+            .lock()
+            .await
+            .track_frame(&self.device, frame.timestamp);
+
+        let arp_packet = match ArpPacket::new(ethernet_packet.payload()) {
+            Some(arp_packet) => arp_packet,
+            None => {
+                let length = ethernet_packet.payload().len();
+                let anomalies = vec![Anomaly::Malformed { length }];
+                self.report(frame, ethernet_packet, None, anomalies).await;
+                return None;
+            }
+        };
+        let operation = arp_packet.get_operation();
+        let target_mac = arp_packet.get_target_hw_addr();
+        let target_ip = arp_packet.get_target_proto_addr();
+        let sender_mac = arp_packet.get_sender_hw_addr();
+        let sender_ip = arp_packet.get_sender_proto_addr();
 
-                let arp_packet = match ArpPacket::new(ethernet_packet.payload()) {
-                    Some(arp_packet) => arp_packet,
-                    None => continue,
-                };
-                let operation = arp_packet.get_operation();
-                let _target_mac = arp_packet.get_target_hw_addr();
-                let target_ip = arp_packet.get_target_proto_addr();
-                let sender_mac = arp_packet.get_sender_hw_addr();
-                let sender_ip = arp_packet.get_sender_proto_addr();
-
-                if operation == ArpOperations::Reply {
-                    info!("ARP Reply\n[Listener] {} is at {}", sender_ip, sender_mac);
-                    let mut arp_cache = self.arp_cache.lock().await;
-                    arp_cache.update(ArpEntry::new(sender_ip, sender_mac));
-                } else if operation == ArpOperations::Request && sender_ip == target_ip {
-                    info!(
-                        "ARP Annoncement\n[Listener] {} is at {}",
-                        sender_ip, sender_mac
+        let (anomalies, usable) = self.validator.inspect(ethernet_packet, &arp_packet);
+        self.report(frame, ethernet_packet, Some(sender_ip.into()), anomalies)
+            .await;
+        if !usable {
+            return None;
+        }
+        // Our own scans are no news
+        if operation == ArpOperations::Request && self.local_mac != Some(sender_mac) {
+            self.arp_cache.lock().await.track_request(
+                &self.device,
+                sender_mac,
+                target_ip.into(),
+                frame.timestamp,
+            );
+        }
+
+        let mut unsolicited = false;
+        let source = if operation == ArpOperations::Reply {
+            info!("ARP Reply\n[Listener] {} is at {}", sender_ip, sender_mac);
+            // Gratuitous replies announce a binding, nobody has to ask
+            unsolicited = sender_ip != target_ip
+                && !self.requests.lock().unwrap().answers(
+                    target_mac,
+                    sender_ip.into(),
+                    frame.timestamp,
+                );
+            match self.local_mac {
+                Some(local_mac) if local_mac == target_mac => BindingSource::Scan,
+                _ => BindingSource::Reply,
+            }
+        } else if operation == ArpOperations::Request && sender_ip.is_unspecified() {
+            info!(
+                "ARP Probe\n[Listener] {} probed by {}",
+                target_ip, sender_mac
+            );
+            self.requests
+                .lock()
+                .unwrap()
+                .request(sender_mac, target_ip.into(), frame.timestamp);
+            let probe = ArpEntry::new(
+                target_ip.into(),
+                sender_mac,
+                BindingSource::Probe,
+                frame.timestamp,
+            )
+            .with_device(&self.device);
+            self.arp_cache.lock().await.address_probe(probe);
+            return None;
+        } else if operation == ArpOperations::Request && sender_ip == target_ip {
+            info!(
+                "ARP Annoncement\n[Listener] {} is at {}",
+                sender_ip, sender_mac
+            );
+            BindingSource::Announcement
+        } else if operation == ArpOperations::Request {
+            debug!("ARP Request\n[Listener] {} is at {}", sender_ip, sender_mac);
+            self.requests
+                .lock()
+                .unwrap()
+                .request(sender_mac, target_ip.into(), frame.timestamp);
+            BindingSource::Request
+        } else {
+            return None;
+        };
+
+        let entry = ArpEntry::new(sender_ip.into(), sender_mac, source, frame.timestamp)
+            .with_device(&self.device);
+        let mut arp_cache = self.arp_cache.lock().await;
+        if unsolicited {
+            arp_cache.unsolicited_reply(&entry, target_ip.into());
+        }
+        let result = arp_cache.update(entry.clone());
+        Some((entry, result))
+    }
+
+    /// Neighbor Discovery, the IPv6 counterpart of ARP: solicitations are
+    /// requests, advertisements replies or announcements
+    async fn handle_ndp(
+        &mut self,
+        frame: &Frame,
+        ethernet_packet: &EthernetPacket<'_>,
+    ) -> Option<(ArpEntry, ArpCacheUpdateResult)> {
+        let message = match NdpMessage::parse(ethernet_packet) {
+            Ok(Some(message)) => message,
+            Ok(None) => return None,
+            Err(anomaly) => {
+                self.report(frame, ethernet_packet, None, vec![anomaly])
+                    .await;
+                return None;
+            }
+        };
+        let (anomalies, usable) = self.validator.inspect_ndp(ethernet_packet, &message);
+        let ip = Some(message.source.into());
+        self.report(frame, ethernet_packet, ip, anomalies).await;
+        if !usable {
+            return None;
+        }
+        // Solicitations for unicast destinations may leave the option out
+        let mac = message.mac.unwrap_or(ethernet_packet.get_source());
+
+        let mut unsolicited = false;
+        let (ip, source) = match message.kind {
+            NdpKind::NeighborSolicit { target } if message.source.is_unspecified() => {
+                info!("NDP DAD\n[Listener] {} probed by {}", target, mac);
+                self.requests
+                    .lock()
+                    .unwrap()
+                    .request(mac, target.into(), frame.timestamp);
+                let probe =
+                    ArpEntry::new(target.into(), mac, BindingSource::Probe, frame.timestamp)
+                        .with_device(&self.device);
+                self.arp_cache.lock().await.address_probe(probe);
+                return None;
+            }
+            NdpKind::NeighborSolicit { target } => {
+                debug!("NDP Solicit\n[Listener] {} is at {}", message.source, mac);
+                self.requests
+                    .lock()
+                    .unwrap()
+                    .request(mac, target.into(), frame.timestamp);
+                if self.local_mac != Some(mac) {
+                    self.arp_cache.lock().await.track_request(
+                        &self.device,
+                        mac,
+                        target.into(),
+                        frame.timestamp,
                     );
-                    let mut arp_cache = self.arp_cache.lock().await;
-                    arp_cache.update(ArpEntry::new(sender_ip, sender_mac));
                 }
+                (message.source, BindingSource::Request)
             }
+            NdpKind::NeighborAdvert {
+                target,
+                solicited: true,
+            } => {
+                info!("NDP Advert\n[Listener] {} is at {}", target, mac);
+                // Solicited advertisements are unicast to the requester
+                unsolicited = !self.requests.lock().unwrap().answers(
+                    ethernet_packet.get_destination(),
+                    target.into(),
+                    frame.timestamp,
+                );
+                match self.local_mac {
+                    Some(local_mac) if local_mac == ethernet_packet.get_destination() => {
+                        (target, BindingSource::Scan)
+                    }
+                    _ => (target, BindingSource::Reply),
+                }
+            }
+            NdpKind::NeighborAdvert { target, .. } => {
+                info!("NDP Announcement\n[Listener] {} is at {}", target, mac);
+                (target, BindingSource::Announcement)
+            }
+            NdpKind::RouterAdvert { lifetime, prefixes } => {
+                info!(
+                    "NDP Router Advert\n[Listener] {} is at {}",
+                    message.source, mac
+                );
+                let entry = ArpEntry::new(
+                    message.source.into(),
+                    mac,
+                    BindingSource::RouterAdvert,
+                    frame.timestamp,
+                )
+                .with_device(&self.device);
+                self.arp_cache
+                    .lock()
+                    .await
+                    .router_advert(&entry, lifetime, prefixes);
+                (message.source, BindingSource::RouterAdvert)
+            }
+        };
+
+        let entry =
+            ArpEntry::new(ip.into(), mac, source, frame.timestamp).with_device(&self.device);
+        let mut arp_cache = self.arp_cache.lock().await;
+        if unsolicited {
+            arp_cache.unsolicited_reply(&entry, message.destination.into());
+        }
+        let result = arp_cache.update(entry.clone());
+        Some((entry, result))
+    }
+
+    /// Publish the anomalies found in `frame`, throttled by kind and sender
+    async fn report(
+        &mut self,
+        frame: &Frame,
+        ethernet_packet: &EthernetPacket<'_>,
+        ip: Option<IpAddr>,
+        anomalies: Vec<Anomaly>,
+    ) {
+        if anomalies.is_empty() {
+            return;
+        }
+        let sender = ethernet_packet.get_source();
+        let arp_cache = self.arp_cache.lock().await;
+        for anomaly in anomalies {
+            let count = match self.validator.count(&anomaly, sender, frame.timestamp) {
+                Some(count) => count,
+                None => continue,
+            };
+            warn!("Anomaly on {}: {}", self.device, anomaly);
+            arp_cache.emit(ArpEvent::Anomaly {
+                context: EventContext::new(frame.timestamp, &self.device, None),
+                ip,
+                anomaly,
+                count,
+                frame: to_hex(&frame.data),
+            });
         }
     }
 }
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    const DEVICE: &str = "eth0";
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, last)
+    }
+
+    /// Host `sender` asking for host `target`
+    fn request(sender: u8, target: u8) -> Vec<u8> {
+        arp_frame(
+            ArpOperations::Request,
+            mac_addr(sender),
+            MacAddr::broadcast(),
+            (mac_addr(sender), ip(sender)),
+            (MacAddr::zero(), ip(target)),
+        )
+    }
+
+    /// Host `sender` answering host `target`
+    fn reply(sender: u8, target: u8) -> Vec<u8> {
+        arp_frame(
+            ArpOperations::Reply,
+            mac_addr(sender),
+            mac_addr(target),
+            (mac_addr(sender), ip(sender)),
+            (mac_addr(target), ip(target)),
+        )
+    }
+
+    /// Run `frames` through a listener, returns the cache and the names of
+    /// the events published
+    async fn listen(frames: Vec<Vec<u8>>) -> (ArpCache, Vec<&'static str>) {
+        let arp_cache: ArpCacheMutex = Arc::new(Mutex::new(ArpCache::empty(false)));
+        let mut events = arp_cache.lock().await.subscribe();
+        let source = MemoryPacketSource::from(frames);
+        let mut listener = NetArpListener::with_source(Box::new(source), arp_cache.clone());
+        listener.device = DEVICE.to_string();
+        listener.packet_handler().await.unwrap();
+
+        let names = event_names(&mut events);
+        let arp_cache = arp_cache.lock().await.clone();
+        (arp_cache, names)
+    }
+
+    fn source_of(arp_cache: &ArpCache, last: u8) -> Option<BindingSource> {
+        arp_cache
+            .entries()
+            .iter()
+            .find(|entry| *entry.ip() == IpAddr::from(ip(last)))
+            .map(|entry| entry.source())
+    }
+
+    #[tokio::test]
+    async fn reply_to_request() {
+        let (arp_cache, events) = listen(vec![request(1, 2), reply(2, 1)]).await;
+        assert_eq!(source_of(&arp_cache, 1), Some(BindingSource::Request));
+        assert_eq!(source_of(&arp_cache, 2), Some(BindingSource::Reply));
+        assert_eq!(events, ["NewHost", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn reply_nobody_asked_for() {
+        let (arp_cache, events) = listen(vec![reply(2, 1)]).await;
+        assert_eq!(source_of(&arp_cache, 2), Some(BindingSource::Reply));
+        assert_eq!(events, ["UnsolicitedReply", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn reply_to_another_requester() {
+        let (_, events) = listen(vec![request(1, 2), reply(2, 3)]).await;
+        assert_eq!(events, ["NewHost", "UnsolicitedReply", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn unsolicited_replies_throttled() {
+        let (_, events) = listen(vec![reply(2, 1), reply(2, 1), reply(2, 3)]).await;
+        assert_eq!(events, ["UnsolicitedReply", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn announcement() {
+        let (arp_cache, events) = listen(vec![request(3, 3)]).await;
+        assert_eq!(source_of(&arp_cache, 3), Some(BindingSource::Announcement));
+        assert_eq!(events, ["GratuitousArp", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn known_host_seen_again() {
+        let (arp_cache, events) = listen(vec![request(3, 3), request(3, 4)]).await;
+        assert_eq!(arp_cache.entries().len(), 1);
+        assert_eq!(arp_cache.entries()[0].hits(), 2);
+        assert_eq!(events, ["GratuitousArp", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn headers_disagree() {
+        let mut forged = request(1, 2);
+        forged[6..12].copy_from_slice(&mac_addr(9).octets());
+        let (arp_cache, events) = listen(vec![forged.clone(), forged]).await;
+        assert_eq!(source_of(&arp_cache, 1), Some(BindingSource::Request));
+        assert_eq!(events, ["Anomaly", "NewHost"]);
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_are_skipped() {
+        let mut truncated = reply(2, 1);
+        truncated.truncate(30);
+        let (arp_cache, events) = listen(vec![vec![0; 6], truncated]).await;
+        assert!(arp_cache.entries().is_empty());
+        assert_eq!(events, ["Anomaly"]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{iter, net::IpAddr, time::Duration};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use pnet::util::MacAddr;
+use tokio::{sync::broadcast::error::RecvError, time};
+
+use crate::event::{ArpEvent, EventContext, ProbeVerdict};
+
+use super::*;
+
+/// How long replies to a probe are collected
+const PROBE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Conflicting binding to verify
+struct Probe {
+    ip: IpAddr,
+    expected: Vec<MacAddr>,
+    claimed: MacAddr,
+    /// Conflict on a pinned gateway
+    gateway: bool,
+}
+
+impl NetArpProber {
+    pub fn new(sender: NetArpSenderMutex, arp_cache: ArpCacheMutex, events: EventSender) -> Self {
+        Self {
+            sender,
+            arp_cache,
+            events,
+        }
+    }
+
+    /// Probe every gateway conflict and MAC change seen on the sender
+    /// interface
+    pub async fn event_handler(self) {
+        let device = self.sender.lock().await.device().to_string();
+        let mut events = self.events.subscribe();
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("{} events missed by the prober", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if event.context().interface != device {
+                continue;
+            }
+            let probe = match event {
+                ArpEvent::GatewayChanged {
+                    ip,
+                    expected,
+                    actual,
+                    ..
+                } => Probe {
+                    ip,
+                    expected,
+                    claimed: actual,
+                    gateway: true,
+                },
+                ArpEvent::MacChanged {
+                    ip,
+                    old,
+                    new,
+                    verifying: true,
+                    ..
+                } => Probe {
+                    ip,
+                    expected: vec![old],
+                    claimed: new,
+                    gateway: false,
+                },
+                _ => continue,
+            };
+            tokio::spawn(self.clone().probe(device.clone(), probe));
+        }
+    }
+
+    /// Ask `expected` and `claimed` who holds `ip`, then report who answered
+    async fn probe(self, device: String, probe: Probe) {
+        let Probe {
+            ip,
+            mut expected,
+            claimed,
+            gateway,
+        } = probe;
+        // Neighbor Discovery bindings are not probed
+        let target = match ip {
+            IpAddr::V4(target) => target,
+            IpAddr::V6(_) => return,
+        };
+        // Replies to a probe in flight keep raising conflicts, other MACs
+        // claiming the IP meanwhile are merged into it
+        if !self
+            .arp_cache
+            .lock()
+            .await
+            .start_probe(&device, ip, claimed)
+        {
+            return;
+        }
+        info!("Probing {} on {}", ip, device);
+        {
+            let mut sender = self.sender.lock().await;
+            for mac in expected.iter().chain(iter::once(&claimed)) {
+                if let Err(e) = sender.probe(target, *mac) {
+                    error!("Failed to probe {} at {}: {e}", ip, mac);
+                }
+            }
+        }
+
+        time::sleep(PROBE_WINDOW).await;
+        let (verdict, answers) = {
+            let mut arp_cache = self.arp_cache.lock().await;
+            let (answers, merged) = arp_cache.finish_probe(&device, ip);
+            for mac in merged {
+                if mac != claimed && !expected.contains(&mac) {
+                    expected.push(mac);
+                }
+            }
+            let verdict = ProbeVerdict::classify(claimed, &answers);
+            // Gateway pins never move on their own
+            if verdict == ProbeVerdict::Migration && !gateway {
+                arp_cache.migrate(&device, ip, claimed);
+            }
+            (verdict, answers)
+        };
+        let _ = self.events.send(ArpEvent::ProbeCompleted {
+            context: EventContext::new(Utc::now(), &device, None),
+            ip,
+            expected,
+            claimed,
+            answers,
+            verdict,
+        });
+    }
+}
@@ -1,8 +1,9 @@
-use std::net::IpAddr;
+use std::{io, time::Duration};
 
-use log::info;
+use log::{debug, error, info, warn};
 use pnet::{
     datalink::{self, Channel::Ethernet, NetworkInterface},
+    ipnetwork::Ipv4Network,
     packet::{
         arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket},
         ethernet::{EtherTypes, MutableEthernetPacket},
@@ -11,19 +12,37 @@ use pnet::{
     util::MacAddr,
 };
 
+use tokio::{sync::watch, time};
+
+use crate::{
+    config::{Config, ScanConfig},
+    event::{ArpEvent, EventContext},
+};
+
 use super::*;
 
+/// Requests sent in a row before pausing, the listener socket overflows
+/// and loses replies otherwise
+const SCAN_BURST: usize = 128;
+const SCAN_PAUSE: Duration = Duration::from_millis(20);
+
 impl NetArpSender {
-    pub fn new(interface: &NetworkInterface) -> Self {
-        let network_addr = interface
+    pub fn new(interface: &NetworkInterface, requests: PendingRequestsMutex) -> Self {
+        // IPv6 neighbours are learned from Neighbor Discovery, not scanned
+        let networks: Vec<Ipv4Network> = interface
             .ips
-            .first()
-            .expect("No network ip avaible")
-            .to_owned();
-        let source_ip = match network_addr.ip() {
-            IpAddr::V4(ipv4) => ipv4,
-            IpAddr::V6(_) => unimplemented!(),
-        };
+            .iter()
+            .filter_map(|network| match network {
+                IpNetwork::V4(network) => Some(*network),
+                IpNetwork::V6(_) => None,
+            })
+            .collect();
+        if networks.is_empty() {
+            warn!(
+                "No IPv4 address on {}, probing from 0.0.0.0",
+                interface.name
+            );
+        }
         let source_mac = interface
             .mac
             .unwrap_or_else(|| panic!("No MAC address for {}", interface.name));
@@ -38,49 +57,330 @@ impl NetArpSender {
         Self {
             tx,
             source_mac,
-            source_ip,
-            network_addr,
+            networks,
+            scan_targets: vec![],
+            max_scan_hosts: 0,
+            device: interface.name.clone(),
+            events: None,
+            requests,
         }
     }
 
-    pub async fn scan_network(&mut self) -> std::io::Result<()> {
-        info!("Starting host scan on {}", self.network_addr);
+    /// Apply the scan settings that can change while running
+    pub fn configure(&mut self, scan: &ScanConfig) {
+        self.scan_targets = scan.targets.clone();
+        self.max_scan_hosts = scan.max_hosts;
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
 
-        // Very nice network address range traversal from ipnetwork
-        for target_ip in self.network_addr.iter() {
-            // Unwrapp IpAddr to Ipv4Addr
-            let target_ip = match target_ip {
-                IpAddr::V4(addr) => addr,
-                IpAddr::V6(_) => panic!("Ipv6 unsupported yet"),
-            };
+    /// Address of the interface on the subnet of `target`, the primary one
+    /// otherwise. Requests from 0.0.0.0 are RFC 5227 probes, still answered
+    fn source_ip(&self, target: Ipv4Addr) -> Ipv4Addr {
+        self.networks
+            .iter()
+            .find(|network| network.contains(target))
+            .or(self.networks.first())
+            .map_or(Ipv4Addr::UNSPECIFIED, |network| network.ip())
+    }
+
+    /// Ranges to scan, the configured targets on the interface networks or
+    /// every IPv4 network of the interface. Ranges over `max_scan_hosts`
+    /// addresses are refused unless `confirmed`
+    fn scan_plan(&self, confirmed: bool) -> std::io::Result<Vec<IpNetwork>> {
+        let ranges = scan_ranges(&self.networks, &self.scan_targets);
+        if ranges.is_empty() && !self.scan_targets.is_empty() {
+            debug!("No scan target on {}", self.device);
+            return Ok(ranges);
+        }
+        if ranges.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no IPv4 network on {}", self.device),
+            ));
+        }
+        if !confirmed && self.max_scan_hosts > 0 {
+            if let Some(range) = ranges
+                .iter()
+                .find(|range| range_size(range) > self.max_scan_hosts)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{range} holds {} addresses, over scan.max_hosts ({}), \
+                         confirm with S in the TUI or --large-scan",
+                        range_size(range),
+                        self.max_scan_hosts
+                    ),
+                ));
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Unicast request asking `mac` whether it holds `ip`
+    pub fn probe(&mut self, ip: Ipv4Addr, mac: MacAddr) -> std::io::Result<()> {
+        debug!("Probing {} at {}", ip, mac);
+        self.request(ip, mac)
+    }
 
-            let mut ethernet_buffer = [0u8; 42];
-            let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
+    fn request(&mut self, target_ip: Ipv4Addr, destination: MacAddr) -> std::io::Result<()> {
+        let mut ethernet_buffer = [0u8; 42];
+        let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
 
-            ethernet_packet.set_destination(MacAddr::broadcast());
-            ethernet_packet.set_source(self.source_mac);
-            ethernet_packet.set_ethertype(EtherTypes::Arp);
+        ethernet_packet.set_destination(destination);
+        ethernet_packet.set_source(self.source_mac);
+        ethernet_packet.set_ethertype(EtherTypes::Arp);
 
-            let mut arp_buffer = [0u8; 28];
-            // TODO Error invalid ARP packet when Option is None
-            let mut arp_packet = MutableArpPacket::new(&mut arp_buffer).unwrap();
+        let mut arp_buffer = [0u8; 28];
+        // TODO Error invalid ARP packet when Option is None
+        let mut arp_packet = MutableArpPacket::new(&mut arp_buffer).unwrap();
 
-            arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
-            arp_packet.set_protocol_type(EtherTypes::Ipv4);
-            arp_packet.set_hw_addr_len(6);
-            arp_packet.set_proto_addr_len(4);
-            arp_packet.set_operation(ArpOperations::Request);
-            arp_packet.set_sender_hw_addr(self.source_mac);
-            arp_packet.set_sender_proto_addr(self.source_ip);
-            arp_packet.set_target_hw_addr(MacAddr::broadcast());
-            arp_packet.set_target_proto_addr(target_ip);
+        arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_packet.set_protocol_type(EtherTypes::Ipv4);
+        arp_packet.set_hw_addr_len(6);
+        arp_packet.set_proto_addr_len(4);
+        arp_packet.set_operation(ArpOperations::Request);
+        arp_packet.set_sender_hw_addr(self.source_mac);
+        arp_packet.set_sender_proto_addr(self.source_ip(target_ip));
+        arp_packet.set_target_hw_addr(destination);
+        arp_packet.set_target_proto_addr(target_ip);
 
-            // Smooth
-            ethernet_packet.set_payload(arp_packet.packet_mut());
+        // Smooth
+        ethernet_packet.set_payload(arp_packet.packet_mut());
 
-            self.tx.send_to(ethernet_packet.packet(), None).unwrap()?;
+        self.requests
+            .lock()
+            .unwrap()
+            .request(self.source_mac, target_ip.into(), Utc::now());
+        self.tx.send_to(ethernet_packet.packet(), None).unwrap()
+    }
+}
+
+/// Scan the configured targets on the interface networks, or every IPv4
+/// network of the interface. Ranges over `max_scan_hosts` addresses are
+/// refused unless `confirmed`
+pub async fn scan_network(sender: &NetArpSenderMutex, confirmed: bool) -> std::io::Result<()> {
+    let ranges = sender.lock().await.scan_plan(confirmed)?;
+    for range in ranges {
+        scan(sender, range).await?;
+    }
+    Ok(())
+}
+
+/// Send a request to every address of `range`. The sender is released
+/// between bursts, probes must not wait for the scan to end
+pub async fn scan(sender: &NetArpSenderMutex, range: IpNetwork) -> std::io::Result<()> {
+    let IpNetwork::V4(network) = range else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot scan {range}, IPv6 hosts are learned from Neighbor Discovery"),
+        ));
+    };
+    info!("Starting host scan on {}", range);
+    let mut requests = 0;
+
+    // Very nice network address range traversal from ipnetwork
+    let mut targets = network.iter();
+    loop {
+        let mut sender = sender.lock().await;
+        let mut burst = 0;
+        for target_ip in targets.by_ref().take(SCAN_BURST) {
+            burst += 1;
+            if sender.networks.iter().any(|own| own.ip() == target_ip) {
+                continue;
+            }
+            sender.request(target_ip, MacAddr::broadcast())?;
+            requests += 1;
+        }
+        if burst < SCAN_BURST {
+            info!("Done sending arp request");
+            if let Some(events) = &sender.events {
+                let _ = events.send(ArpEvent::ScanCompleted {
+                    context: EventContext::new(Utc::now(), &sender.device, None),
+                    range,
+                    requests,
+                });
+            }
+            return Ok(());
         }
-        info!("Done sending arp request");
-        Ok(())
+        drop(sender);
+        time::sleep(SCAN_PAUSE).await;
+    }
+}
+
+/// Ranges to scan on an interface with `networks`: the `targets` overlapping
+/// one of them, every subnet when there are no targets. IPv6 targets are
+/// kept for `scan` to refuse
+fn scan_ranges(networks: &[Ipv4Network], targets: &[IpNetwork]) -> Vec<IpNetwork> {
+    if !targets.is_empty() {
+        return targets
+            .iter()
+            .filter(|target| match target {
+                IpNetwork::V4(target) => networks.iter().any(|own| own.overlaps(*target)),
+                IpNetwork::V6(_) => true,
+            })
+            .copied()
+            .collect();
+    }
+    let mut ranges = vec![];
+    // Secondary addresses often share the subnet of the primary one
+    for network in networks {
+        if let Ok(subnet) = Ipv4Network::new(network.network(), network.prefix()) {
+            if !ranges.contains(&IpNetwork::V4(subnet)) {
+                ranges.push(IpNetwork::V4(subnet));
+            }
+        }
+    }
+    ranges
+}
+
+/// Addresses in `range`, `Ipv4Network::size` overflows for /0
+fn range_size(range: &IpNetwork) -> u64 {
+    match range {
+        IpNetwork::V4(network) => 1u64 << (32 - network.prefix()),
+        IpNetwork::V6(_) => 0,
+    }
+}
+
+/// Scan at the configured interval, following configuration reloads
+pub async fn scan_schedule(sender: NetArpSenderMutex, mut config_rx: watch::Receiver<Config>) {
+    let mut reloads = true;
+    loop {
+        // Scans on demand use the reloaded targets as well
+        let scan = config_rx.borrow_and_update().scan.clone();
+        sender.lock().await.configure(&scan);
+        let interval = scan.interval;
+        if interval == 0 {
+            if config_rx.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs(interval)) => {
+                if let Err(e) = scan_network(&sender, false).await {
+                    error!("Scheduled scan failed {e}");
+                }
+            }
+            changed = config_rx.changed(), if reloads => {
+                reloads = changed.is_ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::Mutex;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    /// Counts the frames sent
+    struct CountingSender(Arc<AtomicUsize>);
+
+    impl datalink::DataLinkSender for CountingSender {
+        fn build_and_send(
+            &mut self,
+            _: usize,
+            _: usize,
+            _: &mut dyn FnMut(&mut [u8]),
+        ) -> Option<io::Result<()>> {
+            unimplemented!()
+        }
+
+        fn send_to(&mut self, _: &[u8], _: Option<NetworkInterface>) -> Option<io::Result<()>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some(Ok(()))
+        }
+    }
+
+    fn sender(sent: &Arc<AtomicUsize>) -> NetArpSenderMutex {
+        Arc::new(Mutex::new(NetArpSender {
+            tx: Box::new(CountingSender(sent.clone())),
+            source_mac: mac_addr(1),
+            networks: networks(&["10.0.0.1/23"]),
+            scan_targets: vec![],
+            max_scan_hosts: 0,
+            device: "eth0".into(),
+            events: None,
+            requests: Default::default(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn scan_releases_the_sender_between_bursts() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sender = sender(&sent);
+        let scan = tokio::spawn({
+            let sender = sender.clone();
+            async move { scan_network(&sender, false).await }
+        });
+        time::sleep(SCAN_PAUSE / 2).await;
+        {
+            let _probe = sender.lock().await;
+            let sent = sent.load(Ordering::SeqCst);
+            assert!(sent > 0 && sent < 511, "{sent} requests sent");
+        }
+        scan.await.unwrap().unwrap();
+        // Every address of the /23 but ours
+        assert_eq!(sent.load(Ordering::SeqCst), 511);
+    }
+
+    fn networks(networks: &[&str]) -> Vec<Ipv4Network> {
+        networks
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect()
+    }
+
+    fn ranges(ranges: &[&str]) -> Vec<IpNetwork> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interface_subnets() {
+        let own = networks(&["10.0.0.5/24", "10.0.0.6/24", "192.168.1.1/30"]);
+        assert_eq!(
+            scan_ranges(&own, &[]),
+            ranges(&["10.0.0.0/24", "192.168.1.0/30"])
+        );
+        assert!(scan_ranges(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn targets_on_the_interface() {
+        let own = networks(&["10.0.0.5/24"]);
+        let targets = ranges(&[
+            "10.0.0.128/25",
+            "10.0.0.0/16",
+            "192.168.1.0/24",
+            "fd00::/64",
+        ]);
+        assert_eq!(
+            scan_ranges(&own, &targets),
+            ranges(&["10.0.0.128/25", "10.0.0.0/16", "fd00::/64"])
+        );
+        assert_eq!(
+            scan_ranges(&networks(&["172.16.0.1/16"]), &targets),
+            ranges(&["fd00::/64"])
+        );
+    }
+
+    #[test]
+    fn range_sizes() {
+        assert_eq!(range_size(&"0.0.0.0/0".parse().unwrap()), 1 << 32);
+        assert_eq!(range_size(&"10.0.0.0/24".parse().unwrap()), 256);
+        assert_eq!(range_size(&"10.0.0.1/32".parse().unwrap()), 1);
     }
 }
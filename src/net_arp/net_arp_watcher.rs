@@ -1,3 +1,5 @@
+use std::error::Error;
+
 use pnet::datalink::{self, NetworkInterface};
 
 use crate::arp_cache::*;
@@ -5,30 +7,66 @@ use crate::arp_cache::*;
 use super::*;
 
 impl NetArpWatcher {
-    pub fn new(name: Option<String>) -> Self {
+    pub fn new(name: Option<String>) -> Result<Self, Box<dyn Error>> {
         if let Some(name) = name {
             match datalink::interfaces()
                 .into_iter()
                 .find(|interface: &NetworkInterface| interface.name == name)
             {
-                Some(interface) => Self { interface },
-                None => panic!("No interface found"),
+                Some(interface) => Ok(Self::with_interface(interface)),
+                None => {
+                    let names: Vec<String> = datalink::interfaces()
+                        .into_iter()
+                        .map(|interface| interface.name)
+                        .collect();
+                    Err(format!(
+                        "No interface named {name}, available interfaces: {}",
+                        names.join(", ")
+                    )
+                    .into())
+                }
             }
         } else {
             for interface in datalink::interfaces().into_iter() {
                 if !interface.is_loopback() {
-                    return Self { interface };
+                    return Ok(Self::with_interface(interface));
                 }
             }
-            panic!("No avaible network interface found");
+            Err("No avaible network interface found".into())
+        }
+    }
+
+    fn with_interface(interface: NetworkInterface) -> Self {
+        Self {
+            interface,
+            requests: Default::default(),
+        }
+    }
+
+    /// One watcher per interface name, the first non loopback one when empty
+    pub fn all(names: &[String]) -> Result<Vec<Self>, Box<dyn Error>> {
+        if names.is_empty() {
+            return Ok(vec![Self::new(None)?]);
         }
+        names
+            .iter()
+            .map(|name| Self::new(Some(name.clone())))
+            .collect()
+    }
+
+    pub fn interfaces() -> Vec<NetworkInterface> {
+        datalink::interfaces()
+    }
+
+    pub fn interface(&self) -> &NetworkInterface {
+        &self.interface
     }
 
     pub fn listener(&self, arp_cache: ArpCacheMutex) -> NetArpListener {
-        NetArpListener::new(&self.interface, arp_cache)
+        NetArpListener::new(&self.interface, arp_cache, self.requests.clone())
     }
 
     pub fn sender(&self) -> NetArpSender {
-        NetArpSender::new(&self.interface)
+        NetArpSender::new(&self.interface, self.requests.clone())
     }
 }
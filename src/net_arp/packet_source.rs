@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use pnet::datalink::{self, Channel::Ethernet, Config, NetworkInterface};
+
+use super::*;
+
+/// Give control back regularly so a quiet capture can still be shut down
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl LivePacketSource {
+    pub fn new(interface: &NetworkInterface) -> Self {
+        let config = Config {
+            read_timeout: Some(READ_TIMEOUT),
+            ..Default::default()
+        };
+        let (_, rx) = match datalink::channel(interface, config) {
+            Ok(Ethernet(tx, rx)) => (tx, rx),
+            Ok(_) => panic!("Unhandled channel type"),
+            Err(e) => panic!(
+                "An error occurred when creating the datalink receiver channel: {}",
+                e
+            ),
+        };
+        Self { rx }
+    }
+}
+
+impl PacketSource for LivePacketSource {
+    fn next_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        let buf = self.rx.next()?;
+        Ok(Some(Frame {
+            timestamp: Utc::now(),
+            data: buf.to_vec(),
+        }))
+    }
+}
+
+impl MemoryPacketSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: Vec<u8>) {
+        self.push_frame(Frame {
+            timestamp: Utc::now(),
+            data,
+        });
+    }
+
+    pub fn push_frame(&mut self, frame: Frame) {
+        self.frames.push_back(frame);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl From<Vec<Vec<u8>>> for MemoryPacketSource {
+    fn from(frames: Vec<Vec<u8>>) -> Self {
+        let mut source = Self::new();
+        for data in frames {
+            source.push(data);
+        }
+        source
+    }
+}
+
+impl PacketSource for MemoryPacketSource {
+    fn next_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        Ok(self.frames.pop_front())
+    }
+}
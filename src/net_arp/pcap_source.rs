@@ -0,0 +1,510 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+
+use super::*;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_OBSOLETE_PACKET: u32 = 0x00000002;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x00000003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Largest snaplen libpcap writes, longer records are corrupted lengths and
+/// must not allocate gigabytes
+const MAX_SNAPLEN: usize = 256 * 1024;
+/// Room for pcapng block headers and options around the packet data
+const MAX_PCAPNG_OVERHEAD: usize = 64 * 1024;
+
+impl PcapPacketSource {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        info!("Replaying capture file {}", path.display());
+        Self::new(BufReader::new(File::open(path)?))
+    }
+
+    pub fn new<R: Read + Send + 'static>(reader: R) -> io::Result<Self> {
+        let mut reader: Box<dyn Read + Send> = Box::new(reader);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let le_magic = u32::from_le_bytes(magic);
+        let be_magic = u32::from_be_bytes(magic);
+
+        if le_magic == PCAPNG_SECTION_HEADER {
+            let mut source = Self {
+                reader,
+                format: PcapFormat::PcapNg { interfaces: vec![] },
+                big_endian: false,
+                done: false,
+            };
+            source.read_section_header()?;
+            return Ok(source);
+        }
+
+        let (big_endian, nanos) = match (le_magic, be_magic) {
+            (PCAP_MAGIC_MICROS, _) => (false, false),
+            (PCAP_MAGIC_NANOS, _) => (false, true),
+            (_, PCAP_MAGIC_MICROS) => (true, false),
+            (_, PCAP_MAGIC_NANOS) => (true, true),
+            _ => return Err(invalid_data("Not a pcap or pcapng file")),
+        };
+
+        // version, thiszone and sigfigs are of no use here
+        let mut header = [0u8; 20];
+        reader.read_exact(&mut header)?;
+        let mut source = Self {
+            reader,
+            format: PcapFormat::Pcap {
+                nanos,
+                link_type: 0,
+                snaplen: MAX_SNAPLEN,
+            },
+            big_endian,
+            done: false,
+        };
+        let snaplen = match source.u32_at(&header, 12) as usize {
+            0 => MAX_SNAPLEN,
+            snaplen => snaplen.min(MAX_SNAPLEN),
+        };
+        let link_type = source.u32_at(&header, 16);
+        if link_type != LINKTYPE_ETHERNET {
+            warn!("Capture link type {link_type} is not Ethernet, frames will be skipped");
+        }
+        source.format = PcapFormat::Pcap {
+            nanos,
+            link_type,
+            snaplen,
+        };
+        Ok(source)
+    }
+
+    fn u16_at(&self, buf: &[u8], offset: usize) -> u16 {
+        let bytes = [buf[offset], buf[offset + 1]];
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = [
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ];
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Read exactly `buf.len()` bytes, `Ok(false)` on a clean end of file
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.reader.read(&mut buf[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read a record of `len` bytes, refused when over `max`
+    fn read_vec(&mut self, len: usize, max: usize) -> io::Result<Vec<u8>> {
+        if len > max {
+            return Err(invalid_data(&format!(
+                "Capture record of {len} bytes exceeds {max} bytes"
+            )));
+        }
+        let mut buf = vec![0u8; len];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn next_pcap_frame(
+        &mut self,
+        nanos: bool,
+        link_type: u32,
+        snaplen: usize,
+    ) -> io::Result<Option<Frame>> {
+        loop {
+            let mut header = [0u8; 16];
+            if !self.read_or_eof(&mut header)? {
+                return Ok(None);
+            }
+            let ts_sec = self.u32_at(&header, 0);
+            let ts_frac = self.u32_at(&header, 4);
+            let incl_len = self.u32_at(&header, 8) as usize;
+            let data = self.read_vec(incl_len, snaplen)?;
+
+            if link_type != LINKTYPE_ETHERNET {
+                continue;
+            }
+            let nsecs = match nanos {
+                true => ts_frac,
+                false => ts_frac.saturating_mul(1_000),
+            };
+            return Ok(Some(Frame {
+                timestamp: timestamp(ts_sec as i64, nsecs),
+                data,
+            }));
+        }
+    }
+
+    /// Parse the remainder of a Section Header Block, its block type has
+    /// already been consumed
+    fn read_section_header(&mut self) -> io::Result<()> {
+        let mut header = [0u8; 8];
+        self.reader.read_exact(&mut header)?;
+        let byte_order = [header[4], header[5], header[6], header[7]];
+        self.big_endian = if u32::from_le_bytes(byte_order) == PCAPNG_BYTE_ORDER_MAGIC {
+            false
+        } else if u32::from_be_bytes(byte_order) == PCAPNG_BYTE_ORDER_MAGIC {
+            true
+        } else {
+            return Err(invalid_data("Invalid pcapng byte order magic"));
+        };
+        let total_len = self.u32_at(&header, 0) as usize;
+        if total_len < 12 {
+            return Err(invalid_data("Invalid pcapng section header length"));
+        }
+        // Version, section length and options
+        self.read_vec(total_len - 12, MAX_PCAPNG_OVERHEAD)?;
+        self.format = PcapFormat::PcapNg { interfaces: vec![] };
+        Ok(())
+    }
+
+    fn next_pcapng_frame(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            let mut block_type = [0u8; 4];
+            if !self.read_or_eof(&mut block_type)? {
+                return Ok(None);
+            }
+            // The section header byte order is unknown until it is parsed
+            if u32::from_le_bytes(block_type) == PCAPNG_SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+            let block_type = self.u32_at(&block_type, 0);
+
+            let mut len = [0u8; 4];
+            self.reader.read_exact(&mut len)?;
+            let total_len = self.u32_at(&len, 0) as usize;
+            if total_len < 12 || !total_len.is_multiple_of(4) {
+                return Err(invalid_data("Invalid pcapng block length"));
+            }
+            // Body followed by the trailing copy of the block length
+            let block = self.read_vec(total_len - 8, MAX_SNAPLEN + MAX_PCAPNG_OVERHEAD)?;
+            let body = &block[..block.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    let interface = self.parse_interface_description(body)?;
+                    if let PcapFormat::PcapNg { interfaces } = &mut self.format {
+                        interfaces.push(interface);
+                    }
+                }
+                PCAPNG_ENHANCED_PACKET | PCAPNG_OBSOLETE_PACKET => {
+                    if body.len() < 20 {
+                        return Err(invalid_data("Truncated pcapng packet block"));
+                    }
+                    let interface_id = match block_type {
+                        PCAPNG_ENHANCED_PACKET => self.u32_at(body, 0) as usize,
+                        _ => self.u16_at(body, 0) as usize,
+                    };
+                    let ts = ((self.u32_at(body, 4) as u64) << 32) | self.u32_at(body, 8) as u64;
+                    let cap_len = self.u32_at(body, 12) as usize;
+                    let data = body
+                        .get(20..20 + cap_len)
+                        .ok_or_else(|| invalid_data("Truncated pcapng packet data"))?;
+
+                    if let Some(frame) = self.pcapng_frame(interface_id, Some(ts), data) {
+                        return Ok(Some(frame));
+                    }
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    if body.len() < 4 {
+                        return Err(invalid_data("Truncated pcapng simple packet block"));
+                    }
+                    // Simple packets carry no captured length, only padding
+                    let orig_len = self.u32_at(body, 0) as usize;
+                    let data = &body[4..body.len().min(4 + orig_len)];
+
+                    if let Some(frame) = self.pcapng_frame(0, None, data) {
+                        return Ok(Some(frame));
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn parse_interface_description(&self, body: &[u8]) -> io::Result<PcapNgInterface> {
+        if body.len() < 8 {
+            return Err(invalid_data("Truncated pcapng interface description"));
+        }
+        let link_type = self.u16_at(body, 0);
+        let mut ts_resolution = 1_000_000;
+
+        let mut offset = 8;
+        while offset + 4 <= body.len() {
+            let code = self.u16_at(body, offset);
+            let len = self.u16_at(body, offset + 2) as usize;
+            offset += 4;
+            if code == PCAPNG_OPTION_END {
+                break;
+            }
+            if code == PCAPNG_OPTION_IF_TSRESOL && len == 1 && offset < body.len() {
+                let tsresol = body[offset];
+                // Most significant bit picks between a power of 2 and of 10
+                ts_resolution = match tsresol & 0x80 {
+                    0 => 10u64.checked_pow(tsresol as u32),
+                    _ => 2u64.checked_pow((tsresol & 0x7f) as u32),
+                }
+                .ok_or_else(|| invalid_data("Unsupported pcapng timestamp resolution"))?;
+            }
+            offset += (len + 3) & !3;
+        }
+
+        if link_type as u32 != LINKTYPE_ETHERNET {
+            warn!("Capture interface with link type {link_type} is not Ethernet, its frames will be skipped");
+        }
+        Ok(PcapNgInterface {
+            link_type,
+            ts_resolution,
+        })
+    }
+
+    fn pcapng_frame(&self, interface_id: usize, ts: Option<u64>, data: &[u8]) -> Option<Frame> {
+        let interfaces = match &self.format {
+            PcapFormat::PcapNg { interfaces } => interfaces,
+            PcapFormat::Pcap { .. } => return None,
+        };
+        let interface = match interfaces.get(interface_id) {
+            Some(interface) => interface,
+            None => {
+                warn!("Packet block for undescribed interface {interface_id}");
+                return None;
+            }
+        };
+        if interface.link_type as u32 != LINKTYPE_ETHERNET {
+            return None;
+        }
+
+        let timestamp = match ts {
+            Some(ts) => {
+                let secs = ts / interface.ts_resolution;
+                let frac = ts % interface.ts_resolution;
+                let nsecs = (frac as u128 * 1_000_000_000 / interface.ts_resolution as u128) as u32;
+                timestamp(secs as i64, nsecs)
+            }
+            None => DateTime::<Utc>::UNIX_EPOCH,
+        };
+        Some(Frame {
+            timestamp,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl PacketSource for PcapPacketSource {
+    fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        if self.done {
+            return Ok(None);
+        }
+        let frame = match self.format {
+            PcapFormat::Pcap {
+                nanos,
+                link_type,
+                snaplen,
+            } => self.next_pcap_frame(nanos, link_type, snaplen),
+            PcapFormat::PcapNg { .. } => self.next_pcapng_frame(),
+        };
+        // A corrupted capture can not be resynchronised, stop there
+        if !matches!(frame, Ok(Some(_))) {
+            self.done = true;
+        }
+        frame
+    }
+}
+
+fn timestamp(secs: i64, nsecs: u32) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, nsecs).unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const FRAME: [u8; 14] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 2, 0, 0, 0, 0, 1, 0x08, 0x06,
+    ];
+
+    fn pcap(snaplen: u32, records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut file = vec![];
+        file.extend(PCAP_MAGIC_MICROS.to_le_bytes());
+        file.extend([2, 0, 4, 0]);
+        file.extend([0; 8]);
+        file.extend(snaplen.to_le_bytes());
+        file.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        for (secs, micros, data) in records {
+            file.extend(secs.to_le_bytes());
+            file.extend(micros.to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(*data);
+        }
+        file
+    }
+
+    fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let total_len = (12 + body.len()) as u32;
+        let mut block = vec![];
+        block.extend(block_type.to_le_bytes());
+        block.extend(total_len.to_le_bytes());
+        block.extend(body);
+        block.extend(total_len.to_le_bytes());
+        block
+    }
+
+    /// Section header and an Ethernet interface with nanosecond timestamps
+    fn pcapng_header() -> Vec<u8> {
+        let mut section = vec![];
+        section.extend(PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend([1, 0, 0, 0]);
+        section.extend((-1i64).to_le_bytes());
+        let mut file = pcapng_block(PCAPNG_SECTION_HEADER, &section);
+
+        let mut interface = vec![];
+        interface.extend((LINKTYPE_ETHERNET as u16).to_le_bytes());
+        interface.extend([0; 6]);
+        interface.extend(PCAPNG_OPTION_IF_TSRESOL.to_le_bytes());
+        interface.extend(1u16.to_le_bytes());
+        interface.extend([9, 0, 0, 0]);
+        interface.extend(PCAPNG_OPTION_END.to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        file.extend(pcapng_block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        file
+    }
+
+    fn enhanced_packet(ts: u64, cap_len: u32, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend(0u32.to_le_bytes());
+        body.extend(((ts >> 32) as u32).to_le_bytes());
+        body.extend((ts as u32).to_le_bytes());
+        body.extend(cap_len.to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+        body.resize((body.len() + 3) & !3, 0);
+        pcapng_block(PCAPNG_ENHANCED_PACKET, &body)
+    }
+
+    fn open(file: Vec<u8>) -> PcapPacketSource {
+        PcapPacketSource::new(Cursor::new(file)).unwrap()
+    }
+
+    #[test]
+    fn pcap_frames() {
+        let mut source = open(pcap(65535, &[(10, 500_000, &FRAME)]));
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!(frame.data, FRAME);
+        assert_eq!(frame.timestamp, timestamp(10, 500_000_000));
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn pcap_truncated_record() {
+        let mut file = pcap(65535, &[(10, 0, &FRAME)]);
+        file.truncate(file.len() - 4);
+        let mut source = open(file);
+        let error = source.next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn pcap_record_over_snaplen() {
+        let mut source = open(pcap(8, &[(10, 0, &FRAME)]));
+        let error = source.next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pcap_record_over_max_snaplen() {
+        let mut file = pcap(0, &[]);
+        file.extend([0; 8]);
+        file.extend(u32::MAX.to_le_bytes());
+        file.extend(u32::MAX.to_le_bytes());
+        let error = open(file).next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn not_a_capture() {
+        let error = PcapPacketSource::new(Cursor::new(vec![0; 24]))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pcapng_frames() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(1_500_000_000, FRAME.len() as u32, &FRAME));
+        let mut source = open(file);
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!(frame.data, FRAME);
+        assert_eq!(frame.timestamp, timestamp(1, 500_000_000));
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn pcapng_truncated_packet_data() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(0, 1500, &FRAME));
+        let error = open(file).next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pcapng_truncated_block() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(0, FRAME.len() as u32, &FRAME));
+        file.truncate(file.len() - 8);
+        let error = open(file).next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn pcapng_oversized_block() {
+        let mut file = pcapng_header();
+        file.extend(PCAPNG_ENHANCED_PACKET.to_le_bytes());
+        file.extend(0x7fff_fff0u32.to_le_bytes());
+        let error = open(file).next_frame().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
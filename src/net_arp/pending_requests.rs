@@ -0,0 +1,85 @@
+use std::{net::IpAddr, time::Duration};
+
+use crate::arp_cache::within;
+
+use super::*;
+
+/// How long a request may wait for its replies
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl PendingRequests {
+    /// `requester` asked who holds `target`
+    pub fn request(&mut self, requester: MacAddr, target: IpAddr, at: DateTime<Utc>) {
+        self.expire(at);
+        self.requests.insert((requester, target), at);
+    }
+
+    /// Whether a reply from `target` to `requester` answers a pending
+    /// request, a request may get several replies
+    pub fn answers(&self, requester: MacAddr, target: IpAddr, at: DateTime<Utc>) -> bool {
+        self.requests
+            .get(&(requester, target))
+            .is_some_and(|requested| within(*requested, at, REPLY_TIMEOUT))
+    }
+
+    /// Drop expired requests, at most once per timeout: scans keep
+    /// thousands pending
+    fn expire(&mut self, at: DateTime<Utc>) {
+        if within(self.swept, at, REPLY_TIMEOUT) {
+            return;
+        }
+        self.swept = at;
+        self.requests
+            .retain(|_, requested| within(*requested, at, REPLY_TIMEOUT));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn replies_match_the_requester() {
+        let requester = mac_addr(1);
+        let other = mac_addr(3);
+        let target = IpAddr::from([10, 0, 0, 2]);
+        let mut requests = PendingRequests::default();
+        requests.request(requester, target, at(0));
+
+        assert!(requests.answers(requester, target, at(1)));
+        assert!(requests.answers(requester, target, at(2)));
+        assert!(!requests.answers(other, target, at(2)));
+        assert!(!requests.answers(requester, [10, 0, 0, 3].into(), at(2)));
+        assert!(!requests.answers(requester, target, at(5)));
+    }
+
+    #[test]
+    fn expired_on_new_requests() {
+        let mut requests = PendingRequests::default();
+        for last in 0..100 {
+            let requester = mac_addr(last);
+            requests.request(requester, [10, 0, 0, last].into(), at(0));
+        }
+        requests.request(MacAddr::zero(), [10, 0, 0, 1].into(), at(10));
+        assert_eq!(requests.requests.len(), 1);
+    }
+
+    #[test]
+    fn expired_once_per_timeout() {
+        let mut requests = PendingRequests::default();
+        let target = IpAddr::from([10, 0, 0, 2]);
+        requests.request(MacAddr::zero(), target, at(10));
+        requests.request(MacAddr::broadcast(), target, at(12));
+        requests.request(MacAddr::zero(), [10, 0, 0, 3].into(), at(14));
+        // Not swept yet, though no longer answered
+        assert!(!requests.answers(MacAddr::zero(), target, at(15)));
+        assert!(requests.answers(MacAddr::broadcast(), target, at(15)));
+        assert_eq!(requests.requests.len(), 3);
+
+        requests.request(MacAddr::zero(), [10, 0, 0, 4].into(), at(15));
+        assert_eq!(requests.requests.len(), 3);
+        assert!(!requests.requests.contains_key(&(MacAddr::zero(), target)));
+    }
+}
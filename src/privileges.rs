@@ -0,0 +1,104 @@
+use std::{error::Error, fs, io::ErrorKind, os::unix::fs::MetadataExt, path::Path};
+
+use caps::{CapSet, Capability, CapsHashSet};
+use log::{info, warn};
+use nix::{
+    sys::prctl,
+    unistd::{chown, setgroups, setresgid, setresuid, Gid, Group, User},
+};
+
+/// Create the directories `user` will write to once privileges are dropped.
+///
+/// Only directories created here are handed over, an existing one is left
+/// alone unless `user` already owns it: `/run` or `/var/lib` must never
+/// change hands because the state or socket path was set to live there.
+pub fn hand_over_dirs(
+    user: &str,
+    group: Option<&str>,
+    dirs: &[&Path],
+) -> Result<(), Box<dyn Error>> {
+    let (user, gid) = account(user, group)?;
+    for dir in dirs {
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::create_dir(dir) {
+            Ok(()) => chown(*dir, Some(user.uid), Some(gid))
+                .map_err(|e| format!("Failed to hand {} over: {e}", dir.display()))?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if fs::metadata(dir)?.uid() != user.uid.as_raw() {
+                    warn!(
+                        "{} was not created by arp-watch, {} will not be able to write there",
+                        dir.display(),
+                        user.name
+                    );
+                }
+            }
+            Err(e) => return Err(format!("Failed to create {}: {e}", dir.display()).into()),
+        }
+    }
+    Ok(())
+}
+
+/// Switch to `user` once the capture sockets are open, keeping CAP_NET_RAW
+/// only when `keep_net_raw`, then forbid regaining anything through exec.
+///
+/// Capabilities are per thread: call this before any other thread is
+/// started.
+pub fn drop_privileges(
+    user: &str,
+    group: Option<&str>,
+    keep_net_raw: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (user, gid) = account(user, group)?;
+
+    let mut kept = CapsHashSet::new();
+    if keep_net_raw {
+        kept.insert(Capability::CAP_NET_RAW);
+    }
+    for cap in caps::runtime::thread_all_supported() {
+        if !kept.contains(&cap) {
+            caps::drop(None, CapSet::Bounding, cap)?;
+        }
+    }
+
+    // Keep the permitted set across the uid change, trimmed right after
+    caps::securebits::set_keepcaps(true)?;
+    setgroups(&[gid])?;
+    setresgid(gid, gid, gid)?;
+    setresuid(user.uid, user.uid, user.uid)?;
+    caps::securebits::set_keepcaps(false)?;
+
+    caps::clear(None, CapSet::Ambient)?;
+    caps::clear(None, CapSet::Inheritable)?;
+    caps::set(None, CapSet::Permitted, &kept)?;
+    caps::set(None, CapSet::Effective, &kept)?;
+    prctl::set_no_new_privs()?;
+
+    let kept: Vec<String> = kept.iter().map(|cap| cap.to_string()).collect();
+    info!(
+        "Running as {} (uid {}, gid {}), capabilities: {}",
+        user.name,
+        user.uid,
+        gid,
+        match kept.is_empty() {
+            true => "none".to_string(),
+            false => kept.join(", "),
+        }
+    );
+    Ok(())
+}
+
+/// `user` and the group to run as, its primary group when `group` is missing
+fn account(user: &str, group: Option<&str>) -> Result<(User, Gid), Box<dyn Error>> {
+    let user = User::from_name(user)?.ok_or_else(|| format!("No user named {user}"))?;
+    let gid = match group {
+        Some(group) => {
+            Group::from_name(group)?
+                .ok_or_else(|| format!("No group named {group}"))?
+                .gid
+        }
+        None => user.gid,
+    };
+    Ok((user, gid))
+}
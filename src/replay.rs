@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, error::Error, net::IpAddr, path::Path, sync::Arc};
+
+use pnet::util::MacAddr;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    arp_cache::{ArpCache, ArpCacheUpdateResult, ArpEntry},
+    cli::OutputFormat,
+    net_arp::{Frame, NetArpListener, PcapPacketSource},
+};
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f %Z";
+
+/// What was learned while replaying a capture file
+#[derive(Debug)]
+pub struct ReplayReport {
+    format: OutputFormat,
+    frames: usize,
+    arp_updates: usize,
+    /// Every MAC seen for an IP, in order of appearance
+    bindings: BTreeMap<IpAddr, Vec<MacAddr>>,
+}
+
+/// Feed a pcap or pcapng file through the listener and ARP cache, printing
+/// every new entry and divergence along the way, then a summary
+pub async fn replay(path: &Path, format: OutputFormat) -> Result<ReplayReport, Box<dyn Error>> {
+    let arp_cache = ArpCache::empty(true);
+    let arp_cache_mutex = Arc::new(Mutex::new(arp_cache));
+
+    let source = PcapPacketSource::open(path)?;
+    let mut listener = NetArpListener::with_source(Box::new(source), arp_cache_mutex);
+    let mut report = ReplayReport::new(format);
+
+    loop {
+        let frame = match listener.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Capture replay stopped early: {e}");
+                break;
+            }
+        };
+        report.frames += 1;
+        if let Some((entry, result)) = listener.handle_frame(&frame).await {
+            report.record(&frame, entry, result);
+        }
+    }
+
+    report.print_summary();
+    Ok(report)
+}
+
+impl ReplayReport {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            frames: 0,
+            arp_updates: 0,
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, frame: &Frame, entry: ArpEntry, result: ArpCacheUpdateResult) {
+        self.arp_updates += 1;
+        let macs = self.bindings.entry(*entry.ip()).or_default();
+        let previous: Vec<String> = macs
+            .iter()
+            .filter(|mac| *mac != entry.mac())
+            .map(|mac| mac.to_string())
+            .collect();
+        if !macs.contains(entry.mac()) {
+            macs.push(*entry.mac());
+        }
+
+        let event = match result {
+            ArpCacheUpdateResult::NewEntry => "NewEntry",
+            ArpCacheUpdateResult::EntryDiff => "EntryDiff",
+            ArpCacheUpdateResult::AlreadyExist => return,
+        };
+
+        match self.format {
+            OutputFormat::Text => {
+                let timestamp = frame.timestamp.format(TIMESTAMP_FORMAT);
+                match result {
+                    ArpCacheUpdateResult::EntryDiff => println!(
+                        "{timestamp} {event} {} was {}, now {}",
+                        entry.ip(),
+                        previous.join(", "),
+                        entry.mac()
+                    ),
+                    _ => println!("{timestamp} {event}  {} at {}", entry.ip(), entry.mac()),
+                }
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "timestamp": frame.timestamp,
+                    "event": event,
+                    "ip": entry.ip(),
+                    "mac": entry.mac(),
+                    "previous": previous,
+                })
+            ),
+        }
+    }
+
+    pub fn print_summary(&self) {
+        match self.format {
+            OutputFormat::Text => self.print_text_summary(),
+            OutputFormat::Json => {
+                let bindings: Vec<_> = self
+                    .bindings
+                    .iter()
+                    .map(|(ip, macs)| {
+                        json!({
+                            "ip": ip,
+                            "macs": macs,
+                            "conflict": macs.len() > 1,
+                        })
+                    })
+                    .collect();
+                println!(
+                    "{}",
+                    json!({
+                        "frames": self.frames,
+                        "arp_updates": self.arp_updates,
+                        "conflicts": self.conflicts().count(),
+                        "bindings": bindings,
+                    })
+                );
+            }
+        }
+    }
+
+    fn print_text_summary(&self) {
+        println!();
+        println!(
+            "{} frames read, {} ARP and Neighbor Discovery messages",
+            self.frames, self.arp_updates
+        );
+        println!(
+            "{} IP to MAC bindings, {} conflicts",
+            self.bindings.len(),
+            self.conflicts().count()
+        );
+        for (ip, macs) in self.bindings.iter() {
+            let macs: Vec<String> = macs.iter().map(|mac| mac.to_string()).collect();
+            let conflict = match macs.len() {
+                1 => "",
+                _ => " CONFLICT",
+            };
+            println!("{:<15} {}{}", ip, macs.join(", "), conflict);
+        }
+    }
+
+    /// IPs claimed by more than one MAC during the capture
+    pub fn conflicts(&self) -> impl Iterator<Item = (&IpAddr, &Vec<MacAddr>)> {
+        self.bindings.iter().filter(|(_, macs)| macs.len() > 1)
+    }
+
+    pub fn bindings(&self) -> &BTreeMap<IpAddr, Vec<MacAddr>> {
+        &self.bindings
+    }
+}
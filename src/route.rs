@@ -0,0 +1,121 @@
+use std::{
+    error::Error,
+    fs,
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use log::warn;
+
+pub const ROUTE_PATH: &str = "/proc/net/route";
+pub const ROUTE6_PATH: &str = "/proc/net/ipv6_route";
+
+/// RTF_GATEWAY, the route goes through a next hop
+const RTF_GATEWAY: u16 = 0x2;
+
+/// Default route next hops of the main routing table, as
+/// `(interface, gateway)`. Parses `/proc/net/route`:
+/// `Iface  Destination  Gateway  Flags  RefCnt  Use  Metric  Mask ...`
+pub fn default_gateways(path: &Path) -> Result<Vec<(String, Ipv4Addr)>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let mut gateways = vec![];
+    for line in content.lines().skip(1) {
+        match parse_default_route(line) {
+            Some(Some(gateway)) => {
+                if !gateways.contains(&gateway) {
+                    gateways.push(gateway);
+                }
+            }
+            Some(None) => continue,
+            None => warn!("Unparsable route line: {}", line),
+        }
+    }
+    Ok(gateways)
+}
+
+/// IPv6 default route next hops, usually the link-local address of the
+/// router advertising them. Parses `/proc/net/ipv6_route`, missing when IPv6
+/// is disabled: `Destination  Length  Source  Length  Next hop  Metric
+/// RefCnt  Use  Flags  Iface`
+pub fn default_gateways_v6(path: &Path) -> Result<Vec<(String, Ipv6Addr)>, Box<dyn Error>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut gateways = vec![];
+    for line in content.lines() {
+        match parse_default_route_v6(line) {
+            Some(Some(gateway)) => {
+                if !gateways.contains(&gateway) {
+                    gateways.push(gateway);
+                }
+            }
+            Some(None) => continue,
+            None => warn!("Unparsable IPv6 route line: {}", line),
+        }
+    }
+    Ok(gateways)
+}
+
+/// `None` when the line cannot be parsed, `Some(None)` for other routes
+fn parse_default_route(line: &str) -> Option<Option<(String, Ipv4Addr)>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 8 {
+        return None;
+    }
+    let destination = u32::from_str_radix(words[1], 16).ok()?;
+    let gateway = u32::from_str_radix(words[2], 16).ok()?;
+    let flags = u16::from_str_radix(words[3], 16).ok()?;
+    let mask = u32::from_str_radix(words[7], 16).ok()?;
+    if destination != 0 || mask != 0 || flags & RTF_GATEWAY == 0 {
+        return Some(None);
+    }
+    // Addresses are printed as the in memory network order value
+    let gateway = Ipv4Addr::from(gateway.to_ne_bytes());
+    Some(Some((words[0].to_string(), gateway)))
+}
+
+/// Same as `parse_default_route`, addresses are printed in network order
+fn parse_default_route_v6(line: &str) -> Option<Option<(String, Ipv6Addr)>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 10 {
+        return None;
+    }
+    let destination = u128::from_str_radix(words[0], 16).ok()?;
+    let length = u8::from_str_radix(words[1], 16).ok()?;
+    let gateway = u128::from_str_radix(words[4], 16).ok()?;
+    let flags = u32::from_str_radix(words[8], 16).ok()?;
+    if destination != 0 || length != 0 || flags & RTF_GATEWAY as u32 == 0 {
+        return Some(None);
+    }
+    Some(Some((words[9].to_string(), Ipv6Addr::from(gateway))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_default_route() {
+        let line = "eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0";
+        let gateway = (String::from("eth0"), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(parse_default_route(line), Some(Some(gateway)));
+        let subnet = "eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0";
+        assert_eq!(parse_default_route(subnet), Some(None));
+        assert_eq!(parse_default_route("eth0 garbage"), None);
+    }
+
+    #[test]
+    fn ipv6_default_route() {
+        let line = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+                    fe800000000000000000000000000001 00000400 00000001 00000000 00000003 eth0";
+        let gateway = (String::from("eth0"), "fe80::1".parse().unwrap());
+        assert_eq!(parse_default_route_v6(line), Some(Some(gateway)));
+        let on_link = "fe800000000000000000000000000000 40 00000000000000000000000000000000 00 \
+                       00000000000000000000000000000000 00000100 00000001 00000000 00000001 eth0";
+        assert_eq!(parse_default_route_v6(on_link), Some(None));
+        assert_eq!(parse_default_route_v6("00 garbage"), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    net::Ipv4Addr,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use pnet::{
+    packet::{
+        arp::{ArpHardwareTypes, ArpOperation, MutableArpPacket},
+        ethernet::{EtherTypes, MutableEthernetPacket},
+        MutablePacket,
+    },
+    util::MacAddr,
+};
+
+use crate::event::EventReceiver;
+
+/// Locally administered MAC of test host `last`
+pub fn mac_addr(last: u8) -> MacAddr {
+    MacAddr::new(2, 0, 0, 0, 0, last)
+}
+
+/// `secs` after the epoch
+pub fn at(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap()
+}
+
+pub fn at_millis(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap()
+}
+
+/// ARP packet from `sender` about `target`, as `(MAC, IP)`, in an Ethernet
+/// frame from `source` to `destination`
+pub fn arp_frame(
+    operation: ArpOperation,
+    source: MacAddr,
+    destination: MacAddr,
+    sender: (MacAddr, Ipv4Addr),
+    target: (MacAddr, Ipv4Addr),
+) -> Vec<u8> {
+    let mut data = vec![0u8; 42];
+    let mut ethernet = MutableEthernetPacket::new(&mut data).unwrap();
+    ethernet.set_destination(destination);
+    ethernet.set_source(source);
+    ethernet.set_ethertype(EtherTypes::Arp);
+
+    let mut arp = MutableArpPacket::new(ethernet.payload_mut()).unwrap();
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(operation);
+    arp.set_sender_hw_addr(sender.0);
+    arp.set_sender_proto_addr(sender.1);
+    arp.set_target_hw_addr(target.0);
+    arp.set_target_proto_addr(target.1);
+    data
+}
+
+/// Names of the events published so far
+pub fn event_names(events: &mut EventReceiver) -> Vec<&'static str> {
+    let mut names = vec![];
+    while let Ok(event) = events.try_recv() {
+        names.push(event.name());
+    }
+    names
+}
+
+/// File `name` in a directory of its own, removed when done
+pub struct TempFile {
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str, content: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("arp-watch-{}-{count}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        Self { path }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
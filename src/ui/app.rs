@@ -1,29 +1,120 @@
-use std::sync::Arc;
+use log::{error, warn};
 
-use tokio::sync::Mutex;
-
-use crate::{arp_cache::ArpCacheMutex, net_arp::NetArpSender};
+use crate::{
+    arp_cache::ArpCacheMutex,
+    event_socket::ClientMessage,
+    net_arp::{net_arp_sender::scan_network, NetArpSenderMutex},
+};
 
 use super::*;
 
 impl App {
-    pub fn new(arp_cache: ArpCacheMutex, net_sender: NetArpSender) -> Self {
+    pub fn new(arp_cache: ArpCacheMutex, net_senders: Vec<NetArpSenderMutex>) -> Self {
+        Self {
+            arp_cache,
+            control: Control::Local(net_senders),
+            interface_filter: None,
+        }
+    }
+
+    /// Show the cache mirrored from a capture daemon
+    pub fn remote(arp_cache: ArpCacheMutex, commands: mpsc::Sender<ClientMessage>) -> Self {
         Self {
             arp_cache,
-            net_sender: Arc::new(Mutex::new(net_sender)),
+            control: Control::Remote(commands),
+            interface_filter: None,
         }
     }
 
     pub async fn toggle_follow_mode(&mut self) {
         let mut arp_cache = self.arp_cache.lock().await;
-        arp_cache.follow_update = !arp_cache.follow_update;
+        match &self.control {
+            Control::Local(_) => arp_cache.follow_update = !arp_cache.follow_update,
+            // Applied once the daemon sends its bindings back
+            Control::Remote(commands) => {
+                let follow_update = !arp_cache.follow_update;
+                if commands
+                    .send(ClientMessage::SetFollow { follow_update })
+                    .await
+                    .is_err()
+                {
+                    error!("Not connected to the capture daemon");
+                }
+            }
+        }
+    }
+
+    /// Scan the filtered interface, or all of them. Large ranges are only
+    /// scanned once `confirmed`
+    pub async fn scan(&self, confirmed: bool) {
+        match &self.control {
+            Control::Local(senders) if senders.is_empty() => {
+                warn!("Passive mode, scans are disabled")
+            }
+            Control::Local(senders) => {
+                for sender_mutex in senders {
+                    let sender_mutex = sender_mutex.clone();
+                    let interface_filter = self.interface_filter.clone();
+                    tokio::spawn(async move {
+                        let device = sender_mutex.lock().await.device().to_string();
+                        if interface_filter.is_some_and(|filter| filter != device) {
+                            return;
+                        }
+                        if let Err(e) = scan_network(&sender_mutex, confirmed).await {
+                            error!("Scan hosts failed {e}");
+                        }
+                    });
+                }
+            }
+            Control::Remote(commands) => {
+                if confirmed {
+                    warn!("Large scans are only allowed with --large-scan on the capture daemon");
+                }
+                let scan = ClientMessage::Scan {
+                    interface: self.interface_filter.clone(),
+                };
+                if commands.send(scan).await.is_err() {
+                    error!("Not connected to the capture daemon");
+                }
+            }
+        }
+    }
+
+    /// Cycle through all interfaces then each interface with bindings
+    pub async fn next_interface_filter(&mut self) {
+        let mut interfaces: Vec<String> = self
+            .arp_cache
+            .lock()
+            .await
+            .entries()
+            .iter()
+            .map(|entry| entry.device().to_string())
+            .collect();
+        interfaces.sort();
+        interfaces.dedup();
+        self.interface_filter = match &self.interface_filter {
+            None => interfaces.first().cloned(),
+            Some(current) => interfaces
+                .iter()
+                .find(|interface| *interface > current)
+                .cloned(),
+        };
     }
 
     pub async fn get_ui_settings(&self) -> UiSettings {
         let arp_cache = self.arp_cache.lock().await;
+        let arp_entries = arp_cache
+            .entries()
+            .into_iter()
+            .filter(|entry| match &self.interface_filter {
+                Some(filter) => entry.device() == filter,
+                None => true,
+            })
+            .collect();
         UiSettings {
-            arp_entries: arp_cache.entries(),
+            arp_entries,
             follow_mode: arp_cache.follow_update,
+            interface_filter: self.interface_filter.clone(),
         }
     }
 }
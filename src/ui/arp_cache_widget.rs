@@ -50,7 +50,7 @@ impl<'a> Widget for ArpCacheWidget<'a> {
 
         let lines: Vec<String> = entries
             .iter()
-            .map(|entry| format!(" {} at {} ", entry.ip(), entry.mac()))
+            .map(|entry| format!(" {} {} at {} ", entry.device(), entry.ip(), entry.mac()))
             .collect();
 
         let max_width = text_area.width as usize;
@@ -6,11 +6,7 @@ use ratatui::{
     widgets::Paragraph,
 };
 
-pub fn button<'a>(
-    letter: char,
-    helper_msg: &'static str,
-    activated: Option<bool>,
-) -> Vec<Span<'a>> {
+pub fn button<'a>(letter: char, helper_msg: &str, activated: Option<bool>) -> Vec<Span<'a>> {
     let color = match activated {
         Some(activated) => match activated {
             true => Color::Green,
@@ -32,10 +28,16 @@ pub fn button<'a>(
 pub fn helper<'a>(ui_settings: &UiSettings) -> Paragraph<'a> {
     let mut line: Line = Line::from(vec![]);
     let mut quit_btn = button('Q', "Quit", None);
-    let mut help_scan = button('S', "Scan hosts", None);
+    let mut help_scan = button('S', "Scan hosts, Shift+S for large ranges", None);
     let mut toggle_follow = button('F', "Allow update", Some(ui_settings.follow_mode));
+    let interface = format!(
+        "Interface: {}",
+        ui_settings.interface_filter.as_deref().unwrap_or("all")
+    );
+    let mut interface_filter = button('I', &interface, None);
     line.spans.append(&mut quit_btn);
     line.spans.append(&mut help_scan);
     line.spans.append(&mut toggle_follow);
+    line.spans.append(&mut interface_filter);
     Paragraph::new(line).alignment(Alignment::Center)
 }
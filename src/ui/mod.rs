@@ -1,5 +1,8 @@
+use tokio::sync::mpsc;
+
 use crate::{
     arp_cache::{ArpCacheMutex, ArpEntry},
+    event_socket::ClientMessage,
     net_arp::NetArpSenderMutex,
 };
 
@@ -10,10 +13,21 @@ pub mod tui;
 
 pub struct App {
     arp_cache: ArpCacheMutex,
-    net_sender: NetArpSenderMutex,
+    control: Control,
+    /// Only show and scan this interface
+    interface_filter: Option<String>,
+}
+
+/// Where scans and settings changes go
+pub enum Control {
+    /// One sender per interface, none in passive mode
+    Local(Vec<NetArpSenderMutex>),
+    /// Capture daemon reached through its event socket
+    Remote(mpsc::Sender<ClientMessage>),
 }
 
 pub struct UiSettings {
     arp_entries: Vec<ArpEntry>,
     follow_mode: bool,
+    interface_filter: Option<String>,
 }
@@ -3,7 +3,6 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use log::error;
 use ratatui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout},
@@ -14,8 +13,6 @@ use ratatui::{
 use std::{error::Error, io, time::Duration};
 use tui_logger::{TuiLoggerLevelOutput, TuiLoggerWidget};
 
-use crate::net_arp::NetArpSenderMutex;
-
 use super::{arp_cache_widget::ArpCacheWidget, helper::helper, App, UiSettings};
 
 pub async fn main_tui(app: App) -> Result<(), Box<dyn Error>> {
@@ -52,17 +49,17 @@ pub async fn run_app<B: Backend>(
                 match key.code {
                     KeyCode::Char('q') => break,
                     KeyCode::Char('s') => {
-                        let sender_mutex: NetArpSenderMutex = app.net_sender.clone();
-                        tokio::spawn(async move {
-                            let mut sender = sender_mutex.lock().await;
-                            if let Err(e) = sender.scan_network().await {
-                                error!("Scan hosts failed {e}");
-                            }
-                        });
+                        app.scan(false).await;
+                    }
+                    KeyCode::Char('S') => {
+                        app.scan(true).await;
                     }
                     KeyCode::Char('f') => {
                         app.toggle_follow_mode().await;
                     }
+                    KeyCode::Char('i') => {
+                        app.next_interface_filter().await;
+                    }
                     _ => continue,
                 };
             }